@@ -0,0 +1,670 @@
+// actions.rs
+// Everything the user can do from the keyboard or the buttons
+// Keys are looked up in a binding table that the config can override
+
+extern crate glium;
+extern crate serde;
+
+use glium::glutin::event::{ModifiersState, VirtualKeyCode};
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+
+// Names are what the config uses, e.g. `next_image = ["Right"]`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+	Quit,
+	NextImage,
+	PreviousImage,
+	FirstImage,
+	LastImage,
+	NextPage,
+	PreviousPage,
+	ZoomIn,
+	ZoomOut,
+	ResetView,
+	RotateCw,
+	RotateCcw,
+	PauseAnimation,
+	NextFrame,
+	PreviousFrame,
+	FasterAnimation,
+	SlowerAnimation,
+	ExposureUp,
+	ExposureDown,
+	GammaUp,
+	GammaDown,
+	NextToneMapping,
+	ResetHdr,
+	ToggleFullscreen,
+	ToggleButtons,
+	ToggleDebug,
+	ToggleMetadata,
+	ToggleExample,
+}
+
+impl Action {
+	pub const ALL: [Action; 28] = [
+		Action::Quit,
+		Action::NextImage,
+		Action::PreviousImage,
+		Action::FirstImage,
+		Action::LastImage,
+		Action::NextPage,
+		Action::PreviousPage,
+		Action::ZoomIn,
+		Action::ZoomOut,
+		Action::ResetView,
+		Action::RotateCw,
+		Action::RotateCcw,
+		Action::PauseAnimation,
+		Action::NextFrame,
+		Action::PreviousFrame,
+		Action::FasterAnimation,
+		Action::SlowerAnimation,
+		Action::ExposureUp,
+		Action::ExposureDown,
+		Action::GammaUp,
+		Action::GammaDown,
+		Action::NextToneMapping,
+		Action::ResetHdr,
+		Action::ToggleFullscreen,
+		Action::ToggleButtons,
+		Action::ToggleDebug,
+		Action::ToggleMetadata,
+		Action::ToggleExample,
+	];
+
+	// Same as in the config
+	pub fn name(self) -> String {
+		serde_json::to_value(self)
+			.ok()
+			.and_then(|name| name.as_str().map(str::to_string))
+			.unwrap_or_default()
+	}
+
+	// Shown in tooltips
+	pub fn description(self) -> &'static str {
+		match self {
+			Action::Quit => "Quit",
+			Action::NextImage => "Next image",
+			Action::PreviousImage => "Previous image",
+			Action::FirstImage => "First image",
+			Action::LastImage => "Last image",
+			Action::NextPage => "Next page of multi-page images",
+			Action::PreviousPage => "Previous page of multi-page images",
+			Action::ZoomIn => "Zoom in",
+			Action::ZoomOut => "Zoom out",
+			Action::ResetView => "Reset zoom, pan and rotation",
+			Action::RotateCw => "Rotate clockwise",
+			Action::RotateCcw => "Rotate counter clockwise",
+			Action::PauseAnimation => "Pause or play the animation",
+			Action::NextFrame => "Next frame, pauses the animation",
+			Action::PreviousFrame => "Previous frame, pauses the animation",
+			Action::FasterAnimation => "Play animations faster",
+			Action::SlowerAnimation => "Play animations slower",
+			Action::ExposureUp => "Brighter HDR images",
+			Action::ExposureDown => "Darker HDR images",
+			Action::GammaUp => "More gamma for HDR images",
+			Action::GammaDown => "Less gamma for HDR images",
+			Action::NextToneMapping => "Next tone mapping for HDR images",
+			Action::ResetHdr => "Reset exposure, gamma and tone mapping",
+			Action::ToggleFullscreen => "Toggle fullscreen",
+			Action::ToggleButtons => "Toggle buttons",
+			Action::ToggleDebug => "Toggle debug menu",
+			Action::ToggleMetadata => "Toggle metadata",
+			Action::ToggleExample => "Toggle example window",
+		}
+	}
+
+	fn default_keys(self) -> &'static [&'static str] {
+		match self {
+			Action::Quit => &["Escape"],
+			Action::NextImage => &["Right", "PageDown"],
+			Action::PreviousImage => &["Left", "PageUp"],
+			Action::FirstImage => &["Home"],
+			Action::LastImage => &["End"],
+			Action::NextPage => &["Ctrl+Right", "Ctrl+PageDown"],
+			Action::PreviousPage => &["Ctrl+Left", "Ctrl+PageUp"],
+			Action::ZoomIn => &["Plus", "Equals", "NumpadAdd"],
+			Action::ZoomOut => &["Minus", "NumpadSubtract"],
+			Action::ResetView => &["0"],
+			Action::RotateCw => &["R"],
+			Action::RotateCcw => &["Shift+R"],
+			Action::PauseAnimation => &["P"],
+			Action::NextFrame => &["Period"],
+			Action::PreviousFrame => &["Comma"],
+			Action::FasterAnimation => &["RBracket"],
+			Action::SlowerAnimation => &["LBracket"],
+			Action::ExposureUp => &["E"],
+			Action::ExposureDown => &["Shift+E"],
+			Action::GammaUp => &["G"],
+			Action::GammaDown => &["Shift+G"],
+			Action::NextToneMapping => &["T"],
+			Action::ResetHdr => &["Shift+T"],
+			Action::ToggleFullscreen => &["F11"],
+			Action::ToggleButtons => &["Space"],
+			Action::ToggleDebug => &["F2"],
+			Action::ToggleMetadata => &["M"],
+			Action::ToggleExample => &["F1"],
+		}
+	}
+}
+
+// `[keybindings]` in the config
+// Actions that aren't listed keep their default keys unless another action took them, `[]` unbinds one
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(
+	try_from = "BTreeMap<String, Vec<String>>",
+	into = "BTreeMap<String, Vec<String>>"
+)]
+pub struct Keybindings(pub BTreeMap<Action, Vec<String>>);
+
+impl Default for Keybindings {
+	fn default() -> Keybindings {
+		Keybindings::from(BTreeMap::new())
+	}
+}
+
+impl From<BTreeMap<Action, Vec<String>>> for Keybindings {
+	fn from(mut map: BTreeMap<Action, Vec<String>>) -> Keybindings {
+		// Keys the user picked win over the defaults of other actions,
+		// so rebinding `Right` doesn't need next_image unbound first
+		let taken: Vec<Vec<KeyPress>> = map
+			.values()
+			.flatten()
+			.filter_map(|key| parse_chord(key).ok())
+			.collect();
+
+		for action in Action::ALL {
+			map.entry(action).or_insert_with(|| {
+				action
+					.default_keys()
+					.iter()
+					.filter(|key| {
+						parse_chord(key)
+							.is_ok_and(|chord| !taken.iter().any(|other| clashes(other, &chord)))
+					})
+					.map(|key| key.to_string())
+					.collect()
+			});
+		}
+
+		Keybindings(map)
+	}
+}
+
+// toml can only read and write string keys
+impl TryFrom<BTreeMap<String, Vec<String>>> for Keybindings {
+	type Error = String;
+
+	fn try_from(map: BTreeMap<String, Vec<String>>) -> Result<Keybindings, String> {
+		let mut actions = BTreeMap::new();
+
+		for (name, keys) in map {
+			let action = Action::ALL
+				.into_iter()
+				.find(|action| action.name() == name)
+				.ok_or_else(|| {
+					format!(
+						"`{}` is not an action, use one of {}",
+						name,
+						Action::ALL.map(Action::name).join(", ")
+					)
+				})?;
+			actions.insert(action, keys);
+		}
+
+		Ok(Keybindings::from(actions))
+	}
+}
+
+impl From<Keybindings> for BTreeMap<String, Vec<String>> {
+	fn from(keybindings: Keybindings) -> BTreeMap<String, Vec<String>> {
+		keybindings
+			.0
+			.into_iter()
+			.map(|(action, keys)| (action.name(), keys))
+			.collect()
+	}
+}
+
+impl Keybindings {
+	// Keys bound to an action as written in the config, for tooltips
+	pub fn keys(&self, action: Action) -> String {
+		self.0
+			.get(&action)
+			.map(|keys| keys.join(", "))
+			.unwrap_or_default()
+	}
+}
+
+// One key with the modifiers held down, e.g. `Ctrl+Shift+O`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct KeyPress {
+	pub key: VirtualKeyCode,
+	pub modifiers: ModifiersState,
+}
+
+impl KeyPress {
+	// Modifiers and key are joined with `+`, case doesn't matter
+	pub fn parse(s: &str) -> Result<KeyPress, String> {
+		let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+		let key = parts.pop().unwrap_or_default();
+
+		let mut modifiers = ModifiersState::empty();
+		for modifier in parts {
+			modifiers |= match modifier.to_lowercase().as_str() {
+				"ctrl" | "control" => ModifiersState::CTRL,
+				"shift" => ModifiersState::SHIFT,
+				"alt" => ModifiersState::ALT,
+				"super" | "logo" | "win" | "cmd" => ModifiersState::LOGO,
+				_ => {
+					return Err(format!(
+						"`{}` is not a modifier, use Ctrl, Shift, Alt or Super",
+						modifier
+					))
+				}
+			};
+		}
+
+		let key = key_from_name(key).ok_or_else(|| format!("`{}` is not a key", key))?;
+
+		Ok(KeyPress { key, modifiers })
+	}
+
+	fn without_shift(self) -> KeyPress {
+		KeyPress {
+			key: self.key,
+			modifiers: self.modifiers - ModifiersState::SHIFT,
+		}
+	}
+}
+
+// Parsed binding table plus the keys of an unfinished chord
+#[derive(Default)]
+pub struct Bindings {
+	// Chords are keys pressed one after another, e.g. `G G`
+	bindings: Vec<(Vec<KeyPress>, Action)>,
+	pending: Vec<KeyPress>,
+	last_press: Option<std::time::Instant>,
+}
+
+impl Bindings {
+	// A chord has to be finished within this time
+	const CHORD_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+	pub fn new(keybindings: &Keybindings) -> Result<Bindings, String> {
+		let mut bindings: Vec<(Vec<KeyPress>, Action)> = Vec::new();
+
+		for (action, keys) in &keybindings.0 {
+			let name = action.name();
+
+			for key in keys {
+				let chord = parse_chord(key).map_err(|e| format!("keybindings.{}: {}", name, e))?;
+
+				// Defaults that clash are already dropped, so these are the user's own keys
+				if let Some((_, other)) = bindings.iter().find(|(other, _)| clashes(other, &chord))
+				{
+					return Err(format!(
+						"keybindings.{}: `{}` clashes with a key of {}",
+						name,
+						key,
+						other.name()
+					));
+				}
+
+				bindings.push((chord, *action));
+			}
+		}
+
+		Ok(Bindings {
+			bindings,
+			pending: Vec::new(),
+			last_press: None,
+		})
+	}
+
+	// Feed a key press, returns the action once a binding is complete
+	pub fn press(&mut self, press: KeyPress) -> Option<Action> {
+		// Modifiers only count together with another key
+		if is_modifier(press.key) {
+			return None;
+		}
+
+		let now = std::time::Instant::now();
+		if self
+			.last_press
+			.is_none_or(|last| now - last > Bindings::CHORD_TIMEOUT)
+		{
+			self.pending.clear();
+		}
+		self.last_press = Some(now);
+
+		// Exact modifiers first, then without shift
+		// so `Plus` works on layouts where it needs shift
+		let mut pending = self.pending.clone();
+		pending.push(press);
+		let mut result = self.lookup(&pending);
+		if result.is_none() && press.modifiers.shift() {
+			pending.pop();
+			pending.push(press.without_shift());
+			result = self.lookup(&pending);
+		}
+
+		match result {
+			Some(Ok(action)) => {
+				self.pending.clear();
+				Some(action)
+			}
+			// Part of a chord, wait for the next key
+			Some(Err(())) => {
+				self.pending = pending;
+				None
+			}
+			// A broken chord shouldn't swallow the key that broke it
+			None if !self.pending.is_empty() => {
+				self.pending.clear();
+				self.press(press)
+			}
+			None => None,
+		}
+	}
+
+	// Ok(action) for a full match, Err(()) for the start of a chord
+	fn lookup(&self, pending: &[KeyPress]) -> Option<Result<Action, ()>> {
+		let mut prefix = false;
+
+		for (chord, action) in &self.bindings {
+			if chord.as_slice() == pending {
+				return Some(Ok(*action));
+			}
+			prefix |= chord.starts_with(pending);
+		}
+
+		if prefix {
+			Some(Err(()))
+		} else {
+			None
+		}
+	}
+}
+
+// Key presses separated by spaces, e.g. `Ctrl+K Ctrl+H`
+fn parse_chord(key: &str) -> Result<Vec<KeyPress>, String> {
+	let chord = key
+		.split_whitespace()
+		.map(KeyPress::parse)
+		.collect::<Result<Vec<KeyPress>, String>>()?;
+	if chord.is_empty() {
+		return Err("empty key".to_string());
+	}
+
+	Ok(chord)
+}
+
+// `G` and `G G` can't both work, the first one always wins
+fn clashes(chord: &[KeyPress], other: &[KeyPress]) -> bool {
+	chord.starts_with(other) || other.starts_with(chord)
+}
+
+fn is_modifier(key: VirtualKeyCode) -> bool {
+	matches!(
+		key,
+		VirtualKeyCode::LShift
+			| VirtualKeyCode::RShift
+			| VirtualKeyCode::LControl
+			| VirtualKeyCode::RControl
+			| VirtualKeyCode::LAlt
+			| VirtualKeyCode::RAlt
+			| VirtualKeyCode::LWin
+			| VirtualKeyCode::RWin
+	)
+}
+
+// Key names are the same as glutin's, plus a few aliases
+fn key_from_name(name: &str) -> Option<VirtualKeyCode> {
+	macro_rules! keys {
+		($($key:ident),*) => {
+			match name.to_lowercase().as_str() {
+				"0" => Some(VirtualKeyCode::Key0),
+				"1" => Some(VirtualKeyCode::Key1),
+				"2" => Some(VirtualKeyCode::Key2),
+				"3" => Some(VirtualKeyCode::Key3),
+				"4" => Some(VirtualKeyCode::Key4),
+				"5" => Some(VirtualKeyCode::Key5),
+				"6" => Some(VirtualKeyCode::Key6),
+				"7" => Some(VirtualKeyCode::Key7),
+				"8" => Some(VirtualKeyCode::Key8),
+				"9" => Some(VirtualKeyCode::Key9),
+				"esc" => Some(VirtualKeyCode::Escape),
+				"enter" => Some(VirtualKeyCode::Return),
+				"backspace" => Some(VirtualKeyCode::Back),
+				$(lower if lower == stringify!($key).to_lowercase() => Some(VirtualKeyCode::$key),)*
+				_ => None,
+			}
+		};
+	}
+
+	keys!(
+		Key1,
+		Key2,
+		Key3,
+		Key4,
+		Key5,
+		Key6,
+		Key7,
+		Key8,
+		Key9,
+		Key0,
+		A,
+		B,
+		C,
+		D,
+		E,
+		F,
+		G,
+		H,
+		I,
+		J,
+		K,
+		L,
+		M,
+		N,
+		O,
+		P,
+		Q,
+		R,
+		S,
+		T,
+		U,
+		V,
+		W,
+		X,
+		Y,
+		Z,
+		Escape,
+		F1,
+		F2,
+		F3,
+		F4,
+		F5,
+		F6,
+		F7,
+		F8,
+		F9,
+		F10,
+		F11,
+		F12,
+		F13,
+		F14,
+		F15,
+		F16,
+		F17,
+		F18,
+		F19,
+		F20,
+		F21,
+		F22,
+		F23,
+		F24,
+		Snapshot,
+		Scroll,
+		Pause,
+		Insert,
+		Home,
+		Delete,
+		End,
+		PageDown,
+		PageUp,
+		Left,
+		Up,
+		Right,
+		Down,
+		Back,
+		Return,
+		Space,
+		Numpad0,
+		Numpad1,
+		Numpad2,
+		Numpad3,
+		Numpad4,
+		Numpad5,
+		Numpad6,
+		Numpad7,
+		Numpad8,
+		Numpad9,
+		NumpadAdd,
+		NumpadDivide,
+		NumpadDecimal,
+		NumpadComma,
+		NumpadEnter,
+		NumpadEquals,
+		NumpadMultiply,
+		NumpadSubtract,
+		Apostrophe,
+		Asterisk,
+		At,
+		Backslash,
+		Colon,
+		Comma,
+		Equals,
+		Grave,
+		LBracket,
+		RBracket,
+		Minus,
+		Period,
+		Plus,
+		Semicolon,
+		Slash,
+		Tab
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn key(s: &str) -> KeyPress {
+		KeyPress::parse(s).unwrap()
+	}
+
+	fn configured(keys: &[(Action, &[&str])]) -> Result<Bindings, String> {
+		let map = keys
+			.iter()
+			.map(|(action, keys)| (*action, keys.iter().map(|key| key.to_string()).collect()))
+			.collect::<BTreeMap<Action, Vec<String>>>();
+		Bindings::new(&Keybindings::from(map))
+	}
+
+	#[test]
+	fn parse_key_press() {
+		let press = key("ctrl + Shift+o");
+		assert_eq!(press.key, VirtualKeyCode::O);
+		assert_eq!(
+			press.modifiers,
+			ModifiersState::CTRL | ModifiersState::SHIFT
+		);
+		assert_eq!(key("Cmd+Plus").modifiers, ModifiersState::LOGO);
+		assert_eq!(key("0").key, VirtualKeyCode::Key0);
+
+		assert!(KeyPress::parse("Hyper+Q").is_err());
+		assert!(KeyPress::parse("Foo").is_err());
+		assert!(KeyPress::parse("Ctrl+").is_err());
+		assert!(parse_chord(" ").is_err());
+	}
+
+	#[test]
+	fn defaults_are_valid() {
+		assert!(Bindings::new(&Keybindings::default()).is_ok());
+	}
+
+	#[test]
+	fn configured_keys_replace_clashing_defaults() {
+		let mut bindings = configured(&[(Action::RotateCw, &["Right"])]).unwrap();
+		assert_eq!(bindings.press(key("Right")), Some(Action::RotateCw));
+		assert_eq!(bindings.press(key("PageDown")), Some(Action::NextImage));
+		assert_eq!(bindings.press(key("R")), None);
+
+		// `G G` takes `G` away from gamma_up
+		let mut bindings = configured(&[(Action::FirstImage, &["G G"])]).unwrap();
+		assert_eq!(bindings.press(key("G")), None);
+		assert_eq!(bindings.press(key("G")), Some(Action::FirstImage));
+	}
+
+	#[test]
+	fn configured_keys_clash() {
+		assert!(configured(&[(Action::Quit, &["Q"]), (Action::NextImage, &["Q Q"])]).is_err());
+		assert!(configured(&[(Action::Quit, &["Q"]), (Action::NextImage, &["Q"])]).is_err());
+	}
+
+	#[test]
+	fn chords() {
+		let mut bindings = configured(&[(Action::FirstImage, &["Home", "Ctrl+G Ctrl+G"])]).unwrap();
+		assert_eq!(bindings.press(key("Ctrl+G")), None);
+		assert_eq!(bindings.press(key("Ctrl+G")), Some(Action::FirstImage));
+		assert_eq!(bindings.press(key("Home")), Some(Action::FirstImage));
+		// Modifiers alone don't break a chord
+		assert_eq!(bindings.press(key("Ctrl+G")), None);
+		assert_eq!(
+			bindings.press(KeyPress {
+				key: VirtualKeyCode::LControl,
+				modifiers: ModifiersState::CTRL,
+			}),
+			None
+		);
+		assert_eq!(bindings.press(key("Ctrl+G")), Some(Action::FirstImage));
+	}
+
+	#[test]
+	fn chord_timeout() {
+		let mut bindings = configured(&[(Action::FirstImage, &["Ctrl+G Ctrl+G"])]).unwrap();
+		assert_eq!(bindings.press(key("Ctrl+G")), None);
+		bindings.last_press = Some(std::time::Instant::now() - 2 * Bindings::CHORD_TIMEOUT);
+		// Starts a new chord instead of finishing the old one
+		assert_eq!(bindings.press(key("Ctrl+G")), None);
+		assert_eq!(bindings.press(key("Ctrl+G")), Some(Action::FirstImage));
+	}
+
+	#[test]
+	fn broken_chord_replays_key() {
+		let mut bindings = configured(&[(Action::FirstImage, &["Ctrl+G Ctrl+G"])]).unwrap();
+		assert_eq!(bindings.press(key("Ctrl+G")), None);
+		assert_eq!(bindings.press(key("Right")), Some(Action::NextImage));
+		assert!(bindings.pending.is_empty());
+	}
+
+	#[test]
+	fn shift_fallback() {
+		let mut bindings = Bindings::new(&Keybindings::default()).unwrap();
+		assert_eq!(bindings.press(key("Shift+Plus")), Some(Action::ZoomIn));
+		// Bindings with shift win over the fallback
+		assert_eq!(bindings.press(key("Shift+R")), Some(Action::RotateCcw));
+		assert_eq!(bindings.press(key("R")), Some(Action::RotateCw));
+		assert_eq!(
+			bindings.press(key("Ctrl+Shift+Right")),
+			Some(Action::NextPage)
+		);
+	}
+}
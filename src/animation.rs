@@ -0,0 +1,257 @@
+// animation.rs
+// Frames of animated GIF, APNG and WebP and when to show them
+// Still images are a single frame that never changes
+
+use crate::svg;
+use crate::texture::ImageTexture;
+
+use std::time::{Duration, Instant};
+
+pub struct Frame {
+	pub texture: ImageTexture,
+	pub delay: Duration,
+}
+
+// Which frame is due, apart from the textures so it works without a GPU
+struct Timing {
+	delays: Vec<Duration>,
+	current: usize,
+	// How long the current frame has been up, goes down by whole delays
+	// so slow draws don't make the animation drift
+	shown: Duration,
+	paused: bool,
+}
+
+impl Timing {
+	fn new(delays: Vec<Duration>) -> Timing {
+		Timing {
+			delays,
+			current: 0,
+			shown: Duration::ZERO,
+			paused: false,
+		}
+	}
+
+	// True if the current frame changed
+	fn advance(&mut self, elapsed: Duration, speed: f32) -> bool {
+		if self.paused || self.delays.len() < 2 {
+			return false;
+		}
+
+		let start = self.current;
+		self.shown += elapsed;
+		loop {
+			let delay = self.delays[self.current].div_f64(speed as f64);
+			if self.shown < delay {
+				break;
+			}
+			self.shown -= delay;
+			self.current = (self.current + 1) % self.delays.len();
+
+			// A whole loop behind, e.g. after the window was hidden
+			if self.shown > delay * self.delays.len() as u32 {
+				self.shown = Duration::ZERO;
+			}
+		}
+
+		self.current != start
+	}
+
+	fn set_paused(&mut self, paused: bool) {
+		// The frame gets its whole delay again when playing on
+		if self.paused && !paused {
+			self.shown = Duration::ZERO;
+		}
+		self.paused = paused;
+	}
+
+	fn step(&mut self, frames: i32) {
+		self.set_paused(true);
+		self.current =
+			(self.current as i64 + frames as i64).rem_euclid(self.delays.len() as i64) as usize;
+	}
+}
+
+pub struct Animation {
+	textures: Vec<ImageTexture>,
+	timing: Timing,
+	// Of the last update, time in between counts towards the shown frame
+	updated: Instant,
+	// Of the file the frames came from, see pages.rs
+	pub pages: usize,
+	// What happened to the embedded color profile, see color::apply
+	pub profile: Option<String>,
+	// Low resolution stand in while the full image decodes, never cached
+	pub preview: bool,
+	// SVGs, rasterized again when zoomed in
+	pub vector: Option<svg::Vector>,
+	// Scale of the raster of vector that is shown
+	pub raster_scale: f32,
+}
+
+impl Animation {
+	// Playback speed limits, 1.0 is normal
+	pub const MIN_SPEED: f32 = 0.125;
+	pub const MAX_SPEED: f32 = 8.0;
+
+	// Frames must not be empty
+	pub fn new(frames: Vec<Frame>, pages: usize, profile: Option<String>) -> Animation {
+		let (textures, delays) = frames
+			.into_iter()
+			.map(|frame| {
+				// Browsers play tiny delays at 100ms and a lot of GIFs count on that
+				let delay = if frame.delay <= Duration::from_millis(10) {
+					Duration::from_millis(100)
+				} else {
+					frame.delay
+				};
+				(frame.texture, delay)
+			})
+			.unzip();
+
+		Animation {
+			textures,
+			timing: Timing::new(delays),
+			updated: Instant::now(),
+			pages,
+			profile,
+			preview: false,
+			vector: None,
+			raster_scale: 1.0,
+		}
+	}
+
+	pub fn texture(&self) -> &ImageTexture {
+		&self.textures[self.timing.current]
+	}
+
+	// Swap a still image for a sharper version of itself, e.g. a bigger SVG raster
+	pub fn replace_texture(&mut self, texture: ImageTexture) {
+		self.textures[self.timing.current] = texture;
+	}
+
+	pub fn is_animated(&self) -> bool {
+		self.textures.len() > 1
+	}
+
+	// 0 based index of the shown frame
+	pub fn frame(&self) -> usize {
+		self.timing.current
+	}
+
+	pub fn frame_count(&self) -> usize {
+		self.textures.len()
+	}
+
+	// Bytes of video memory of all frames
+	pub fn memory(&self) -> usize {
+		self.textures.iter().map(|texture| texture.memory).sum()
+	}
+
+	// Go to the frames that are due, true if the shown frame changed
+	pub fn update(&mut self, speed: f32) -> bool {
+		let now = Instant::now();
+		let elapsed = now - self.updated;
+		self.updated = now;
+		self.timing.advance(elapsed, speed)
+	}
+
+	pub fn paused(&self) -> bool {
+		self.timing.paused
+	}
+
+	pub fn set_paused(&mut self, paused: bool) {
+		// Time while paused doesn't count
+		self.updated = Instant::now();
+		self.timing.set_paused(paused);
+	}
+
+	// Negative goes back, pauses so the frame stays
+	pub fn step(&mut self, frames: i32) {
+		self.timing.step(frames);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn ms(ms: u64) -> Duration {
+		Duration::from_millis(ms)
+	}
+
+	fn timing() -> Timing {
+		Timing::new(vec![ms(100), ms(200), ms(300)])
+	}
+
+	#[test]
+	fn delays_add_up() {
+		let mut timing = timing();
+		assert!(!timing.advance(ms(60), 1.0));
+		// 60 + 60 is past the first delay, the 20 left count for the second frame
+		assert!(timing.advance(ms(60), 1.0));
+		assert_eq!((timing.current, timing.shown), (1, ms(20)));
+		assert!(!timing.advance(ms(179), 1.0));
+		assert!(timing.advance(ms(1), 1.0));
+		assert_eq!((timing.current, timing.shown), (2, ms(0)));
+	}
+
+	#[test]
+	fn loops() {
+		let mut timing = timing();
+		// Several frames in one slow draw
+		assert!(timing.advance(ms(350), 1.0));
+		assert_eq!((timing.current, timing.shown), (2, ms(50)));
+		assert!(timing.advance(ms(250), 1.0));
+		assert_eq!((timing.current, timing.shown), (0, ms(0)));
+
+		// Far behind starts over from the frame it got to
+		assert!(timing.advance(ms(10_000), 1.0));
+		assert_eq!(timing.shown, ms(0));
+	}
+
+	#[test]
+	fn speed() {
+		let mut timing = timing();
+		assert!(timing.advance(ms(50), 2.0));
+		assert_eq!(timing.current, 1);
+		assert!(!timing.advance(ms(150), 0.5));
+	}
+
+	#[test]
+	fn paused() {
+		let mut timing = timing();
+		timing.advance(ms(90), 1.0);
+		timing.set_paused(true);
+		assert!(!timing.advance(ms(1000), 1.0));
+		assert_eq!(timing.current, 0);
+
+		// Playing on gives the frame its whole delay again
+		timing.set_paused(false);
+		assert!(!timing.advance(ms(90), 1.0));
+		assert!(timing.advance(ms(10), 1.0));
+	}
+
+	#[test]
+	fn single_steps() {
+		let mut timing = timing();
+		timing.step(1);
+		assert!(timing.paused);
+		assert_eq!(timing.current, 1);
+		assert!(!timing.advance(ms(1000), 1.0));
+		assert_eq!(timing.current, 1);
+
+		// Both ways wrap around
+		timing.step(-2);
+		assert_eq!(timing.current, 2);
+		timing.step(1);
+		assert_eq!(timing.current, 0);
+	}
+
+	#[test]
+	fn still_images_stay() {
+		let mut timing = Timing::new(vec![ms(100)]);
+		assert!(!timing.advance(ms(1000), 1.0));
+		assert_eq!(timing.current, 0);
+	}
+}
@@ -0,0 +1,173 @@
+// cache.rs
+// Textures of images that were shown or prefetched, so flipping back is instant
+// Least recently used ones are dropped first when the budget runs out
+
+use crate::animation::Animation;
+use crate::loader::ImageKey;
+use crate::settings;
+
+// Anything that takes up video memory
+pub trait Cached {
+	// In bytes
+	fn memory(&self) -> usize;
+}
+
+impl Cached for Animation {
+	fn memory(&self) -> usize {
+		Animation::memory(self)
+	}
+}
+
+pub struct TextureCache<T: Cached = Animation> {
+	// Most recently used last
+	entries: Vec<(ImageKey, T)>,
+}
+
+impl<T: Cached> Default for TextureCache<T> {
+	fn default() -> TextureCache<T> {
+		TextureCache {
+			entries: Vec::new(),
+		}
+	}
+}
+
+impl<T: Cached> TextureCache<T> {
+	pub fn take(&mut self, key: ImageKey) -> Option<T> {
+		let position = self.entries.iter().position(|(k, _)| *k == key)?;
+		Some(self.entries.remove(position).1)
+	}
+
+	pub fn contains(&self, key: ImageKey) -> bool {
+		self.entries.iter().any(|(k, _)| *k == key)
+	}
+
+	// Dropped right away if it doesn't fit
+	pub fn insert(&mut self, key: ImageKey, image: T, budget: usize) {
+		self.entries.retain(|(k, _)| *k != key);
+		if image.memory() <= budget {
+			self.entries.push((key, image));
+		}
+		self.shrink(budget);
+	}
+
+	// Drop the oldest textures until they fit into budget bytes
+	pub fn shrink(&mut self, budget: usize) {
+		while self.memory() > budget {
+			self.entries.remove(0);
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	// Bytes of video memory of all cached textures
+	pub fn memory(&self) -> usize {
+		self.entries.iter().map(|(_, image)| image.memory()).sum()
+	}
+
+	// Bytes the cache may use, settings.cache.budget_mb unless the GPU runs low
+	// free is what the driver reports, the cache itself is already taken out of it
+	pub fn budget(&self, settings: &settings::CacheSettings, free: Option<usize>) -> usize {
+		let budget = settings.budget_mb * 1_000_000;
+
+		match free {
+			Some(free) => {
+				let usable =
+					(self.memory() + free).saturating_sub(settings.keep_free_mb * 1_000_000);
+				budget.min(usable)
+			}
+			None => budget,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Just a size, no texture
+	struct Fake(usize);
+
+	impl Cached for Fake {
+		fn memory(&self) -> usize {
+			self.0
+		}
+	}
+
+	fn keys(cache: &TextureCache<Fake>) -> Vec<ImageKey> {
+		cache.entries.iter().map(|(key, _)| *key).collect()
+	}
+
+	#[test]
+	fn evicts_least_recently_used() {
+		let mut cache = TextureCache::default();
+		cache.insert((0, 0), Fake(40), 100);
+		cache.insert((1, 0), Fake(40), 100);
+		assert_eq!(cache.memory(), 80);
+
+		// Taking and putting back makes it the newest
+		let first = cache.take((0, 0)).unwrap();
+		cache.insert((0, 0), first, 100);
+		cache.insert((2, 0), Fake(40), 100);
+
+		assert_eq!(keys(&cache), vec![(0, 0), (2, 0)]);
+		assert!(!cache.contains((1, 0)));
+		assert_eq!(cache.memory(), 80);
+	}
+
+	#[test]
+	fn too_big_is_dropped() {
+		let mut cache = TextureCache::default();
+		cache.insert((0, 0), Fake(40), 100);
+		cache.insert((1, 0), Fake(101), 100);
+
+		assert_eq!(keys(&cache), vec![(0, 0)]);
+	}
+
+	#[test]
+	fn replaces_existing_key() {
+		let mut cache = TextureCache::default();
+		cache.insert((0, 0), Fake(40), 100);
+		cache.insert((1, 0), Fake(40), 100);
+		cache.insert((0, 0), Fake(60), 100);
+
+		// The old entry doesn't count against the budget anymore
+		assert_eq!(keys(&cache), vec![(1, 0), (0, 0)]);
+		assert_eq!(cache.memory(), 100);
+		assert_eq!(cache.take((0, 0)).map(|image| image.0), Some(60));
+		assert_eq!(cache.len(), 1);
+	}
+
+	#[test]
+	fn shrinks_to_budget() {
+		let mut cache = TextureCache::default();
+		for index in 0..5 {
+			cache.insert((index, 0), Fake(20), 100);
+		}
+
+		cache.shrink(50);
+		assert_eq!(keys(&cache), vec![(3, 0), (4, 0)]);
+		cache.shrink(0);
+		assert_eq!(cache.len(), 0);
+	}
+
+	#[test]
+	fn budget_leaves_room_on_the_gpu() {
+		let settings = settings::CacheSettings {
+			budget_mb: 100,
+			keep_free_mb: 50,
+			..Default::default()
+		};
+		let mut cache = TextureCache::default();
+		cache.insert((0, 0), Fake(30_000_000), usize::MAX);
+
+		// Unknown free memory
+		assert_eq!(cache.budget(&settings, None), 100_000_000);
+		// Plenty free
+		assert_eq!(cache.budget(&settings, Some(1_000_000_000)), 100_000_000);
+		// 30 cached + 40 free - 50 kept
+		assert_eq!(cache.budget(&settings, Some(40_000_000)), 20_000_000);
+		assert_eq!(cache.budget(&settings, Some(0)), 0);
+	}
+}
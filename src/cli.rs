@@ -0,0 +1,281 @@
+// cli.rs
+// Command line interface, everything that happens before a window is made
+
+extern crate clap;
+
+use crate::convert;
+use crate::headless;
+
+use clap::builder::TypedValueParser;
+use clap::{Parser, Subcommand, ValueEnum};
+
+// Exit codes
+// Scripts can tell *why* r-liv failed from these
+pub struct ExitCodes {}
+impl ExitCodes {
+	// Everything went fine
+	pub const SUCCESS: i32 = 0;
+
+	// 1 isn't used, panics abort (SIGABRT) in release builds and exit with 101 in debug ones
+	// 2 is used by clap for bad arguments
+
+	// Input file doesn't exist or can't be read
+	pub const NO_INPUT: i32 = 3;
+
+	// Input file is read but isn't a valid image
+	pub const DECODE: i32 = 4;
+
+	// Output file can't be encoded or written
+	pub const OUTPUT: i32 = 5;
+
+	// No usable OpenGL
+	pub const OPENGL: i32 = 6;
+
+	// Config file is invalid
+	pub const CONFIG: i32 = 7;
+}
+
+// How the image is fitted into the window before zooming
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum FitMode {
+	// Whole image is visible (default)
+	Contain,
+	// Image covers the whole window, edges may be cut off
+	Cover,
+	// Image width matches window width
+	Width,
+	// Image height matches window height
+	Height,
+	// One image pixel is one screen pixel
+	Original,
+}
+
+#[derive(Parser, Debug)]
+#[clap(
+	name = "r-liv",
+	version,
+	about = "Rust Lean Image Viewer - Fast and configurable image viewer",
+	subcommand_precedence_over_arg = true
+)]
+pub struct Args {
+	// Images or directories to open
+	#[clap(
+		value_name = "IMAGE",
+		help = "Images or directories to open, a single image opens it's whole directory, \
+		        `-` reads from stdin"
+	)]
+	pub images: Vec<String>,
+
+	// Start in fullscreen
+	#[clap(short, long, help = "Start in borderless fullscreen")]
+	pub fullscreen: bool,
+
+	// Initial zoom level
+	#[clap(
+		short,
+		long,
+		value_name = "LEVEL",
+		default_value_t = 1.0,
+		value_parser = parse_zoom,
+		help = "Initial zoom level, 1.0 is the fitted size"
+	)]
+	pub zoom: f32,
+
+	// Initial fit mode
+	#[clap(
+		long,
+		value_name = "MODE",
+		value_enum,
+		default_value_t = FitMode::Contain,
+		help = "How the image is fitted into the window"
+	)]
+	pub fit: FitMode,
+
+	// Configuration file
+	#[clap(
+		short,
+		long,
+		global = true,
+		value_name = "PATH",
+		help = "Configuration file to use instead of $XDG_CONFIG_HOME/r-liv/config.toml"
+	)]
+	pub config: Option<std::path::PathBuf>,
+
+	#[clap(subcommand)]
+	pub command: Option<Command>,
+}
+
+// Headless operations, none of these create a window
+#[derive(Subcommand, Debug)]
+pub enum Command {
+	// Print the image formats r-liv can open
+	#[clap(about = "Print the image formats r-liv can open")]
+	Formats,
+
+	// Print the effective configuration
+	#[clap(about = "Print the effective configuration as TOML")]
+	Config {
+		#[clap(long, help = "Print the defaults, ignoring any config file")]
+		default: bool,
+	},
+
+	// Print image metadata
+	#[clap(about = "Print dimensions, format and EXIF of images without opening a window")]
+	Info {
+		#[clap(
+			value_name = "IMAGE",
+			required = true,
+			help = "Images or directories, `-` reads from stdin"
+		)]
+		images: Vec<String>,
+
+		#[clap(long, help = "Print JSON instead of text")]
+		json: bool,
+	},
+
+	// Batch convert images
+	#[clap(about = "Convert images to another format without opening a window")]
+	Convert {
+		#[clap(
+			value_name = "IMAGE",
+			required = true,
+			help = "Images or directories, `-` reads from stdin"
+		)]
+		images: Vec<String>,
+
+		#[clap(
+			long,
+			value_name = "FORMAT",
+			value_parser = convert::parse_format,
+			help = "Output format, e.g. png, jpg, webp"
+		)]
+		to: image::ImageFormat,
+
+		#[clap(
+			long,
+			value_name = "DIR",
+			help = "Where to write the results, defaults to next to the input"
+		)]
+		out_dir: Option<std::path::PathBuf>,
+
+		#[clap(
+			long,
+			value_name = "1-100",
+			default_value_t = 90,
+			value_parser = clap::value_parser!(u8).range(1..=100),
+			help = "JPEG quality"
+		)]
+		quality: u8,
+
+		#[clap(
+			long,
+			value_name = "SIZE",
+			value_parser = convert::parse_resize,
+			help = "Resize to fit inside WIDTHxHEIGHT or scale by N%"
+		)]
+		resize: Option<convert::Resize>,
+
+		#[clap(
+			long,
+			value_name = "DEGREES",
+			value_parser = clap::builder::PossibleValuesParser::new(["90", "180", "270"])
+				.map(|s| s.parse::<u16>().unwrap()),
+			help = "Rotate clockwise"
+		)]
+		rotate: Option<u16>,
+
+		#[clap(long, value_name = "AXIS", value_enum, help = "Mirror the image")]
+		flip: Option<convert::Flip>,
+
+		#[clap(long, help = "Replace existing output files")]
+		overwrite: bool,
+	},
+
+	// Offscreen render of the view
+	#[clap(about = "Render the view of an image to a file without opening a window")]
+	Render {
+		#[clap(value_name = "IMAGE", help = "Image to render, `-` reads from stdin")]
+		image: String,
+
+		#[clap(
+			long,
+			value_name = "WxH",
+			value_parser = headless::parse_size,
+			help = "Size of the virtual window, defaults to the image size"
+		)]
+		size: Option<(u32, u32)>,
+
+		#[clap(
+			short,
+			long,
+			value_name = "LEVEL",
+			default_value_t = 1.0,
+			value_parser = parse_zoom,
+			help = "Zoom level, 1.0 is the fitted size"
+		)]
+		zoom: f32,
+
+		#[clap(
+			long,
+			value_name = "X,Y",
+			default_value = "0,0",
+			value_parser = headless::parse_offset,
+			allow_hyphen_values = true,
+			help = "Pan in pixels from the center"
+		)]
+		offset: (f32, f32),
+
+		#[clap(
+			long,
+			value_name = "MODE",
+			value_enum,
+			default_value_t = FitMode::Contain,
+			help = "How the image is fitted into the window"
+		)]
+		fit: FitMode,
+
+		#[clap(
+			long,
+			value_name = "DEGREES",
+			default_value = "0",
+			value_parser = clap::builder::PossibleValuesParser::new(["0", "90", "180", "270"])
+				.map(|s| s.parse::<u16>().unwrap()),
+			help = "Rotate clockwise"
+		)]
+		rotate: u16,
+
+		#[clap(
+			short,
+			long,
+			value_name = "PATH",
+			help = "Where to save the render, format is taken from the extension"
+		)]
+		output: std::path::PathBuf,
+	},
+}
+
+// Same limits as the scroll wheel in ui.rs
+fn parse_zoom(s: &str) -> Result<f32, String> {
+	let zoom: f32 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+
+	if !(0.01..=100.0).contains(&zoom) {
+		return Err(format!("zoom must be between 0.01 and 100, got {}", zoom));
+	}
+
+	Ok(zoom)
+}
+
+// `r-liv formats`
+pub fn print_formats() -> i32 {
+	for format in image::ImageFormat::all() {
+		if !format.can_read() || !format.reading_enabled() {
+			continue;
+		}
+
+		println!("{:?}: {}", format, format.extensions_str().join(", "));
+	}
+	println!("Svg: svg, svgz");
+	println!("Raw: cr2, nef, arw, dng (embedded preview)");
+
+	ExitCodes::SUCCESS
+}
@@ -0,0 +1,709 @@
+// color.rs
+// ICC color profiles, e.g. Adobe RGB, Display P3 or ProPhoto
+// Pixels are converted to sRGB or the monitor's profile before they are uploaded
+
+extern crate image;
+extern crate qcms;
+extern crate tiff;
+
+use image::{DynamicImage, ImageBuffer, ImageDecoder, ImageFormat};
+
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Arc;
+
+// Profile images are converted to
+#[derive(Clone)]
+pub struct Target {
+	pub name: String,
+	profile: Arc<qcms::Profile>,
+	// For 16 bit and float images, None if the profile is made of lookup tables
+	shaper: Option<Shaper>,
+}
+
+impl Target {
+	pub fn srgb() -> Target {
+		Target::new(
+			"sRGB".to_string(),
+			qcms::Profile::new_sRGB(),
+			Some(Shaper::srgb()),
+		)
+	}
+
+	// The monitor's profile, e.g. from a calibration tool
+	pub fn load(path: &Path) -> Result<Target, String> {
+		let icc = std::fs::read(path)
+			.map_err(|e| format!("Can't read display profile `{}`: {}", path.display(), e))?;
+
+		let profile = qcms::Profile::new_from_slice(&icc, false)
+			.filter(|_| icc.get(16..20) == Some(&b"RGB "[..]))
+			.ok_or_else(|| format!("`{}` is not an RGB ICC profile", path.display()))?;
+
+		let name = description(&icc).unwrap_or_else(|| {
+			path.file_name()
+				.unwrap_or_default()
+				.to_string_lossy()
+				.to_string()
+		});
+
+		Ok(Target::new(name, profile, Shaper::parse(&icc)))
+	}
+
+	fn new(name: String, mut profile: Box<qcms::Profile>, shaper: Option<Shaper>) -> Target {
+		// Builds the lookup tables once instead of for every image
+		profile.precache_output_transform();
+
+		Target {
+			name,
+			profile: Arc::from(profile),
+			shaper,
+		}
+	}
+}
+
+// Embedded profile of a page, None if there is none
+pub fn icc_profile(format: Option<ImageFormat>, bytes: &[u8], page: usize) -> Option<Vec<u8>> {
+	let cursor = Cursor::new(bytes);
+
+	match format? {
+		ImageFormat::Png => image::codecs::png::PngDecoder::new(cursor)
+			.ok()?
+			.icc_profile(),
+		ImageFormat::Jpeg => image::codecs::jpeg::JpegDecoder::new(cursor)
+			.ok()?
+			.icc_profile(),
+		ImageFormat::WebP => image::codecs::webp::WebPDecoder::new(cursor)
+			.ok()?
+			.icc_profile(),
+		// Every page has its own, see pages.rs
+		ImageFormat::Tiff => {
+			let mut decoder = tiff::decoder::Decoder::new(cursor).ok()?;
+			decoder.seek_to_image(page).ok()?;
+			decoder.get_tag_u8_vec(tiff::tags::Tag::Unknown(34675)).ok()
+		}
+		_ => None,
+	}
+}
+
+// Name for the user, e.g. "Adobe RGB (1998)"
+pub fn name(icc: &[u8]) -> String {
+	description(icc).unwrap_or_else(|| "Unnamed profile".to_string())
+}
+
+// Convert every frame from the embedded profile to target
+// Also returns what happened for the metadata view, None if nothing had to be done
+// Frames that can't be converted are kept as they are
+pub fn apply<T>(
+	frames: Vec<(DynamicImage, T)>,
+	icc: Option<&[u8]>,
+	target: Option<&Target>,
+) -> (Vec<(DynamicImage, T)>, Option<String>) {
+	let target = match target {
+		Some(target) => target,
+		None => {
+			return (
+				frames,
+				icc.map(|icc| format!("{}, not converted", name(icc))),
+			)
+		}
+	};
+
+	// Images without a profile are sRGB, only a monitor profile changes them
+	let (source, gray, name) = match icc {
+		Some(icc) => {
+			let gray = match icc.get(16..20) {
+				Some(b"GRAY") => true,
+				Some(b"RGB ") => false,
+				_ => {
+					let skipped = format!(
+						"{}, not converted: only RGB and gray are supported",
+						name(icc)
+					);
+					return (frames, Some(skipped));
+				}
+			};
+			match qcms::Profile::new_from_slice(icc, false) {
+				Some(profile) => (profile, gray, name(icc)),
+				None => {
+					return (
+						frames,
+						Some(format!("{}, not converted: it's broken", name(icc))),
+					)
+				}
+			}
+		}
+		None if target.profile.is_sRGB() => return (frames, None),
+		None => (qcms::Profile::new_sRGB(), false, "None, sRGB".to_string()),
+	};
+
+	// qcms would cut 16 bit and float images down to 8 bit, so they get the profile's
+	// primaries and curves instead
+	let shapers = match icc {
+		Some(icc) => Shaper::parse(icc),
+		None => Some(Shaper::srgb()),
+	}
+	.zip(target.shaper.as_ref());
+	if shapers.is_none() && frames.iter().any(|(image, _)| !is_8_bit(image)) {
+		let skipped = format!(
+			"{}, not converted: 16 bit and float images need matrix profiles",
+			name
+		);
+		return (frames, Some(skipped));
+	}
+
+	let mut failed = false;
+	let frames = frames
+		.into_iter()
+		.map(|(image, extra)| {
+			let converted = match &shapers {
+				Some((from, to)) if !is_8_bit(&image) => convert_deep(image, from, to),
+				_ => convert(image, &source, gray, target),
+			};
+			match converted {
+				Ok(image) => (image, extra),
+				Err(image) => {
+					failed = true;
+					(image, extra)
+				}
+			}
+		})
+		.collect();
+
+	let applied = if failed {
+		format!("{}, qcms can't convert it to {}", name, target.name)
+	} else {
+		format!("{} to {}", name, target.name)
+	};
+
+	(frames, Some(applied))
+}
+
+fn is_8_bit(image: &DynamicImage) -> bool {
+	let color = image.color();
+	color.bits_per_pixel() == 8 * color.channel_count() as u16
+}
+
+// qcms only converts 8 bits per channel, see convert_deep for the rest
+// Gray profiles turn gray pixels into RGB ones
+// Gives the image back untouched on errors
+fn convert(
+	image: DynamicImage,
+	source: &qcms::Profile,
+	gray: bool,
+	target: &Target,
+) -> Result<DynamicImage, DynamicImage> {
+	use qcms::DataType;
+
+	let alpha = image.color().has_alpha();
+	let (from, to) = match (gray, alpha) {
+		(true, false) => (DataType::Gray8, DataType::RGB8),
+		(true, true) => (DataType::GrayA8, DataType::RGBA8),
+		(false, false) => (DataType::RGB8, DataType::RGB8),
+		(false, true) => (DataType::RGBA8, DataType::RGBA8),
+	};
+
+	let transform =
+		match qcms::Transform::new_to(source, &target.profile, from, to, qcms::Intent::default()) {
+			Some(transform) => transform,
+			None => return Err(image),
+		};
+
+	let (width, height) = (image.width(), image.height());
+	let mut data = match (gray, alpha) {
+		(true, false) => image.into_luma8().into_raw(),
+		(true, true) => image.into_luma_alpha8().into_raw(),
+		(false, false) => image.into_rgb8().into_raw(),
+		(false, true) => image.into_rgba8().into_raw(),
+	};
+	if from == to {
+		transform.apply(&mut data);
+	} else {
+		let mut converted = vec![0; width as usize * height as usize * to.bytes_per_pixel()];
+		transform.convert(&data, &mut converted);
+		data = converted;
+	}
+
+	// The buffer always has the right size
+	if alpha {
+		Ok(DynamicImage::ImageRgba8(
+			ImageBuffer::from_raw(width, height, data).unwrap(),
+		))
+	} else {
+		Ok(DynamicImage::ImageRgb8(
+			ImageBuffer::from_raw(width, height, data).unwrap(),
+		))
+	}
+}
+
+// 16 bit and float images in f32, the same math as qcms but without its 8 bit tables
+// Float images are linear light already, so they only get the matrix and keep values above 1.0
+// Gray images become RGB, gives the image back untouched on errors
+fn convert_deep(
+	image: DynamicImage,
+	source: &Shaper,
+	target: &Shaper,
+) -> Result<DynamicImage, DynamicImage> {
+	let matrix = match inverse(target.matrix) {
+		Some(inverse) => multiply(inverse, source.matrix),
+		None => return Err(image),
+	};
+	let transform = |[r, g, b]: [f32; 3]| matrix.map(|row| row[0] * r + row[1] * g + row[2] * b);
+
+	match image {
+		DynamicImage::ImageRgb32F(mut buffer) => {
+			for pixel in buffer.pixels_mut() {
+				pixel.0 = transform(pixel.0);
+			}
+			Ok(DynamicImage::ImageRgb32F(buffer))
+		}
+		DynamicImage::ImageRgba32F(mut buffer) => {
+			for pixel in buffer.pixels_mut() {
+				let [r, g, b, a] = pixel.0;
+				let [r, g, b] = transform([r, g, b]);
+				pixel.0 = [r, g, b, a];
+			}
+			Ok(DynamicImage::ImageRgba32F(buffer))
+		}
+		image => {
+			// Every 16 bit value once instead of a curve per pixel
+			let linear: Vec<Vec<f32>> = source
+				.curves
+				.iter()
+				.map(|curve| {
+					(0..=u16::MAX)
+						.map(|value| curve.eval(value as f32 / 65535.0))
+						.collect()
+				})
+				.collect();
+			let convert = |pixel: &mut [u16]| {
+				let rgb = transform([0, 1, 2].map(|i| linear[i][pixel[i] as usize]));
+				for (i, value) in rgb.into_iter().enumerate() {
+					let encoded = target.curves[i].invert(value.clamp(0.0, 1.0));
+					pixel[i] = (encoded * 65535.0).round().clamp(0.0, 65535.0) as u16;
+				}
+			};
+
+			if image.color().has_alpha() {
+				let mut buffer = image.into_rgba16();
+				buffer.pixels_mut().for_each(|pixel| convert(&mut pixel.0));
+				Ok(DynamicImage::ImageRgba16(buffer))
+			} else {
+				let mut buffer = image.into_rgb16();
+				buffer.pixels_mut().for_each(|pixel| convert(&mut pixel.0));
+				Ok(DynamicImage::ImageRgb16(buffer))
+			}
+		}
+	}
+}
+
+// Profiles made of three primaries and a curve for each, most RGB ones are
+#[derive(Clone)]
+struct Shaper {
+	// Linear RGB to XYZ, rows are X, Y and Z
+	matrix: [[f32; 3]; 3],
+	curves: [Curve; 3],
+}
+
+impl Shaper {
+	// D50, what ICC profiles are relative to
+	const WHITE: [f32; 3] = [0.9642, 1.0, 0.8249];
+
+	fn srgb() -> Shaper {
+		// Primaries adapted to D50, the same qcms uses
+		let curve = Curve::Parametric([
+			2.4,
+			1.0 / 1.055,
+			0.055 / 1.055,
+			1.0 / 12.92,
+			0.04045,
+			0.0,
+			0.0,
+		]);
+		Shaper {
+			matrix: [
+				[0.4360747, 0.3850649, 0.1430804],
+				[0.2225045, 0.7168786, 0.0606169],
+				[0.0139322, 0.0971045, 0.7141733],
+			],
+			curves: [curve.clone(), curve.clone(), curve],
+		}
+	}
+
+	fn parse(icc: &[u8]) -> Option<Shaper> {
+		let curve = |signature| Curve::parse(find_tag(icc, signature)?);
+
+		match icc.get(16..20)? {
+			// Gray pixels are expanded to RGB first, so a third of white from each channel
+			b"GRAY" => {
+				let gray = curve(b"kTRC")?;
+				Some(Shaper {
+					matrix: Shaper::WHITE.map(|white| [white / 3.0; 3]),
+					curves: [gray.clone(), gray.clone(), gray],
+				})
+			}
+			b"RGB " => {
+				let xyz = |signature| {
+					let tag =
+						find_tag(icc, signature).filter(|tag| tag.get(0..4) == Some(b"XYZ "))?;
+					Some([s15(tag, 8)?, s15(tag, 12)?, s15(tag, 16)?])
+				};
+				let [r, g, b] = [xyz(b"rXYZ")?, xyz(b"gXYZ")?, xyz(b"bXYZ")?];
+				Some(Shaper {
+					matrix: [0, 1, 2].map(|i| [r[i], g[i], b[i]]),
+					curves: [curve(b"rTRC")?, curve(b"gTRC")?, curve(b"bTRC")?],
+				})
+			}
+			_ => None,
+		}
+	}
+}
+
+// Tone response curve, from stored values to linear light
+#[derive(Clone)]
+enum Curve {
+	// g, a, b, c, d, e and f of the ICC type 4 function, every other type fits in it
+	Parametric([f32; 7]),
+	// Evenly spaced from 0 to 1
+	Table(Vec<f32>),
+}
+
+impl Curve {
+	fn parse(tag: &[u8]) -> Option<Curve> {
+		match tag.get(0..4)? {
+			b"curv" => {
+				let value = |i: usize| {
+					Some(u16::from_be_bytes(
+						tag.get(12 + i * 2..14 + i * 2)?.try_into().ok()?,
+					))
+				};
+				match be_u32(tag, 8)? {
+					0 => Some(Curve::Parametric([1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0])),
+					// Just a gamma, 8.8 fixed point
+					1 => {
+						let gamma = value(0)? as f32 / 256.0;
+						(gamma > 0.0)
+							.then_some(Curve::Parametric([gamma, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0]))
+					}
+					count => (0..count)
+						.map(|i| Some(value(i)? as f32 / 65535.0))
+						.collect::<Option<_>>()
+						.map(Curve::Table),
+				}
+			}
+			b"para" => {
+				let kind = u16::from_be_bytes(tag.get(8..10)?.try_into().ok()?) as usize;
+				let count = *[1, 3, 4, 5, 7].get(kind)?;
+				let p = (0..count)
+					.map(|i| s15(tag, 12 + i * 4))
+					.collect::<Option<Vec<f32>>>()?;
+				if p[0] == 0.0 || (kind > 0 && p[1] == 0.0) {
+					return None;
+				}
+
+				Some(Curve::Parametric(match kind {
+					0 => [p[0], 1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+					1 => [p[0], p[1], p[2], 0.0, -p[2] / p[1], 0.0, 0.0],
+					2 => [p[0], p[1], p[2], 0.0, -p[2] / p[1], p[3], p[3]],
+					3 => [p[0], p[1], p[2], p[3], p[4], 0.0, 0.0],
+					_ => [p[0], p[1], p[2], p[3], p[4], p[5], p[6]],
+				}))
+			}
+			_ => None,
+		}
+	}
+
+	fn eval(&self, x: f32) -> f32 {
+		match self {
+			Curve::Parametric([g, a, b, c, d, e, f]) => {
+				if x >= *d {
+					(a * x + b).max(0.0).powf(*g) + e
+				} else {
+					c * x + f
+				}
+			}
+			Curve::Table(table) => {
+				let position = x.clamp(0.0, 1.0) * (table.len() - 1) as f32;
+				let i = (position as usize).min(table.len() - 2);
+				table[i] + (table[i + 1] - table[i]) * (position - i as f32)
+			}
+		}
+	}
+
+	// From linear light back to stored values
+	fn invert(&self, y: f32) -> f32 {
+		match self {
+			Curve::Parametric([g, a, b, c, d, e, f]) => {
+				if y >= c * d + f {
+					((y - e).max(0.0).powf(1.0 / g) - b) / a
+				} else if *c != 0.0 {
+					(y - f) / c
+				} else {
+					0.0
+				}
+			}
+			// Tables only ever go up
+			Curve::Table(table) => {
+				let i = table.partition_point(|&value| value < y);
+				if i == 0 {
+					return 0.0;
+				}
+				if i == table.len() {
+					return 1.0;
+				}
+				let (low, high) = (table[i - 1], table[i]);
+				(i as f32 - 1.0 + (y - low) / (high - low)) / (table.len() - 1) as f32
+			}
+		}
+	}
+}
+
+fn inverse(m: [[f32; 3]; 3]) -> Option<[[f32; 3]; 3]> {
+	// Going around the indices takes care of the signs
+	let cofactor = |r: usize, c: usize| {
+		let (r1, r2, c1, c2) = ((r + 1) % 3, (r + 2) % 3, (c + 1) % 3, (c + 2) % 3);
+		m[r1][c1] * m[r2][c2] - m[r1][c2] * m[r2][c1]
+	};
+	let determinant: f32 = (0..3).map(|c| m[0][c] * cofactor(0, c)).sum();
+
+	(determinant.abs() > f32::EPSILON)
+		.then(|| [0, 1, 2].map(|r| [0, 1, 2].map(|c| cofactor(c, r) / determinant)))
+}
+
+fn multiply(a: [[f32; 3]; 3], b: [[f32; 3]; 3]) -> [[f32; 3]; 3] {
+	[0, 1, 2].map(|r| [0, 1, 2].map(|c| (0..3).map(|i| a[r][i] * b[i][c]).sum()))
+}
+
+fn be_u32(bytes: &[u8], offset: usize) -> Option<usize> {
+	Some(u32::from_be_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?) as usize)
+}
+
+// Signed 16.16 fixed point, what ICC profiles store most numbers as
+fn s15(bytes: &[u8], offset: usize) -> Option<f32> {
+	Some(i32::from_be_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?) as f32 / 65536.0)
+}
+
+// Data of a tag, e.g. `rXYZ`
+fn find_tag<'a>(icc: &'a [u8], signature: &[u8; 4]) -> Option<&'a [u8]> {
+	// Tag table right after the 128 byte header, 12 bytes per tag
+	// The count is from the file, never look past what's actually there
+	let tags = be_u32(icc, 128)?.min(icc.len().saturating_sub(132) / 12);
+	let entry = (0..tags)
+		.map(|i| 132 + i * 12)
+		.find(|&entry| icc.get(entry..entry + 4) == Some(&signature[..]))?;
+	let start = be_u32(icc, entry + 4)?;
+	icc.get(start..start.checked_add(be_u32(icc, entry + 8)?)?)
+}
+
+// Text of the profile description tag
+fn description(icc: &[u8]) -> Option<String> {
+	let tag = find_tag(icc, b"desc")?;
+
+	let text = match tag.get(0..4)? {
+		// ICC v2 textDescriptionType, ASCII with its length first
+		b"desc" => {
+			let length = be_u32(tag, 8)?;
+			String::from_utf8_lossy(tag.get(12..12 + length)?).to_string()
+		}
+		// ICC v4 multiLocalizedUnicodeType, the first language is enough
+		b"mluc" => {
+			if be_u32(tag, 8)? == 0 {
+				return None;
+			}
+			let length = be_u32(tag, 20)?;
+			let offset = be_u32(tag, 24)?;
+			let utf16: Vec<u16> = tag
+				.get(offset..offset + length)?
+				.chunks_exact(2)
+				.map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+				.collect();
+			String::from_utf16_lossy(&utf16)
+		}
+		_ => return None,
+	};
+
+	let text = text.trim_end_matches('\0').trim();
+	(!text.is_empty()).then(|| text.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn description_of_broken_profiles() {
+		// Claims four billion tags but has none
+		let mut icc = vec![0; 132];
+		icc[128..132].copy_from_slice(&u32::MAX.to_be_bytes());
+		assert_eq!(description(&icc), None);
+		assert_eq!(name(&icc[..100]), "Unnamed profile");
+	}
+
+	// Adobe RGB (1998) as a v2 display profile, primaries are adapted to D50
+	fn adobe_rgb() -> Vec<u8> {
+		let xyz = |values: [f32; 3]| {
+			let mut tag = b"XYZ \0\0\0\0".to_vec();
+			for value in values {
+				tag.extend_from_slice(&((value * 65536.0).round() as i32).to_be_bytes());
+			}
+			tag
+		};
+		// Gamma 563/256 in 8.8 fixed point
+		let curve = b"curv\0\0\0\0\0\0\0\x01\x02\x33\0\0".to_vec();
+		let mut desc = b"desc\0\0\0\0\0\0\0\x11Adobe RGB (1998)\0".to_vec();
+		desc.resize(desc.len().next_multiple_of(4), 0);
+		let tags = [
+			(b"desc", desc),
+			(b"rXYZ", xyz([0.6097559, 0.3111242, 0.0194811])),
+			(b"gXYZ", xyz([0.2052401, 0.625656, 0.0608902])),
+			(b"bXYZ", xyz([0.149224, 0.0632197, 0.7448387])),
+			(b"rTRC", curve.clone()),
+			(b"gTRC", curve.clone()),
+			(b"bTRC", curve),
+		];
+
+		let mut icc = vec![0; 128];
+		icc[8..12].copy_from_slice(&[2, 0x10, 0, 0]);
+		icc[12..24].copy_from_slice(b"mntrRGB XYZ ");
+		icc[36..40].copy_from_slice(b"acsp");
+		icc.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+		let mut offset = 132 + tags.len() * 12;
+		for (signature, data) in &tags {
+			icc.extend_from_slice(&signature[..]);
+			icc.extend_from_slice(&(offset as u32).to_be_bytes());
+			icc.extend_from_slice(&(data.len() as u32).to_be_bytes());
+			offset += data.len();
+		}
+		for (_, data) in tags {
+			icc.extend_from_slice(&data);
+		}
+		let size = (icc.len() as u32).to_be_bytes();
+		icc[0..4].copy_from_slice(&size);
+		icc
+	}
+
+	fn rgb16(pixels: &[[u16; 3]]) -> DynamicImage {
+		let data = pixels.concat();
+		DynamicImage::ImageRgb16(ImageBuffer::from_raw(pixels.len() as u32, 1, data).unwrap())
+	}
+
+	fn pixels16(image: &DynamicImage) -> Vec<[u16; 3]> {
+		match image {
+			DynamicImage::ImageRgb16(buffer) => buffer.pixels().map(|pixel| pixel.0).collect(),
+			_ => panic!("not RGB16"),
+		}
+	}
+
+	#[test]
+	fn deep_images_are_converted() {
+		let icc = adobe_rgb();
+		assert!(Shaper::parse(&icc).is_some());
+		let pixels = [
+			[20000, 40000, 10000],
+			[50000, 12000, 30000],
+			[65535, 65535, 65535],
+		];
+
+		let (frames, applied) = apply(
+			vec![(rgb16(&pixels), ())],
+			Some(&icc),
+			Some(&Target::srgb()),
+		);
+		assert_eq!(applied.unwrap(), "Adobe RGB (1998) to sRGB");
+		let converted = pixels16(&frames[0].0);
+		assert_ne!(converted[0], pixels[0]);
+		assert_ne!(converted[1], pixels[1]);
+
+		// Close to what qcms makes of the same colors in 8 bit
+		let shallow = rgb16(&pixels).into_rgb8();
+		let (frames, _) = apply(
+			vec![(DynamicImage::ImageRgb8(shallow), ())],
+			Some(&icc),
+			Some(&Target::srgb()),
+		);
+		let qcms = frames[0].0.to_rgb8();
+		for (deep, shallow) in converted.iter().zip(qcms.pixels()) {
+			for (deep, shallow) in deep.iter().zip(shallow.0) {
+				assert!(
+					(*deep as f32 / 257.0 - shallow as f32).abs() <= 2.0,
+					"{:?}",
+					converted
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn deep_round_trip() {
+		let path = std::env::temp_dir().join(format!("r-liv-adobe-{}.icc", std::process::id()));
+		std::fs::write(&path, adobe_rgb()).unwrap();
+		let adobe = Target::load(&path).unwrap();
+		std::fs::remove_file(&path).unwrap();
+		assert_eq!(adobe.name, "Adobe RGB (1998)");
+
+		// Untagged is sRGB, which fits in Adobe RGB and comes back the same
+		let pixels = [
+			[20000, 40000, 10000],
+			[0, 65535, 300],
+			[65535, 65535, 65535],
+		];
+		let (frames, applied) = apply(vec![(rgb16(&pixels), ())], None, Some(&adobe));
+		assert_eq!(applied.unwrap(), "None, sRGB to Adobe RGB (1998)");
+		let image = frames.into_iter().next().unwrap().0;
+		assert_ne!(pixels16(&image)[0], pixels[0]);
+
+		let (frames, _) = apply(vec![(image, ())], Some(&adobe_rgb()), Some(&Target::srgb()));
+		for (back, pixel) in pixels16(&frames[0].0).iter().zip(pixels) {
+			for (back, value) in back.iter().zip(pixel) {
+				assert!(
+					(*back as i32 - value as i32).abs() <= 8,
+					"{:?} {:?}",
+					back,
+					pixel
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn floats_stay_unclipped() {
+		let image =
+			DynamicImage::ImageRgb32F(ImageBuffer::from_raw(1, 1, vec![2.0, 0.5, 0.1]).unwrap());
+		let (frames, _) = apply(vec![(image, ())], Some(&adobe_rgb()), Some(&Target::srgb()));
+		match &frames[0].0 {
+			// Adobe RGB red is redder than sRGB can be, linear values only get the matrix
+			DynamicImage::ImageRgb32F(buffer) => {
+				let [r, g, b] = buffer.get_pixel(0, 0).0;
+				assert!(
+					r > 2.0 && g > 0.4 && g < 0.6 && b < 0.2,
+					"{} {} {}",
+					r,
+					g,
+					b
+				);
+			}
+			_ => panic!("not RGB32F"),
+		}
+	}
+
+	#[test]
+	fn curves_invert() {
+		let curves = [
+			Curve::Parametric([
+				2.4,
+				1.0 / 1.055,
+				0.055 / 1.055,
+				1.0 / 12.92,
+				0.04045,
+				0.0,
+				0.0,
+			]),
+			Curve::Parametric([2.2, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0]),
+			Curve::Table((0..=255).map(|i| (i as f32 / 255.0).powf(1.8)).collect()),
+		];
+		for curve in curves {
+			for x in [0.0, 0.01, 0.2, 0.5, 0.9, 1.0] {
+				assert!((curve.invert(curve.eval(x)) - x).abs() < 0.001, "{}", x);
+			}
+		}
+	}
+}
@@ -0,0 +1,364 @@
+// convert.rs
+// `r-liv convert`, batch conversion without a window
+// Uses the same decoder as the viewer
+
+use crate::cli;
+use crate::playlist;
+use crate::utils::{DecodeOptions, ImageSource, UiUtils};
+
+use std::path::{Path, PathBuf};
+
+// Parsed `--resize`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Resize {
+	// Fit inside, keeps aspect ratio
+	Fit(u32, u32),
+	// Percent of the original size
+	Percent(f32),
+}
+
+// `WxH` or `N%`
+pub fn parse_resize(s: &str) -> Result<Resize, String> {
+	if let Some(percent) = s.strip_suffix('%') {
+		let percent: f32 = percent
+			.parse()
+			.map_err(|_| format!("`{}` is not a percentage", s))?;
+		if percent <= 0.0 {
+			return Err("percentage must be positive".to_string());
+		}
+		return Ok(Resize::Percent(percent));
+	}
+
+	let (width, height) = s
+		.split_once('x')
+		.ok_or_else(|| format!("`{}` is not WIDTHxHEIGHT or N%", s))?;
+	let width: u32 = width
+		.parse()
+		.map_err(|_| format!("`{}` is not a width", width))?;
+	let height: u32 = height
+		.parse()
+		.map_err(|_| format!("`{}` is not a height", height))?;
+	if width == 0 || height == 0 {
+		return Err("size must not be zero".to_string());
+	}
+
+	Ok(Resize::Fit(width, height))
+}
+
+// Output format by extension, e.g. `png` or `jpg`
+pub fn parse_format(s: &str) -> Result<image::ImageFormat, String> {
+	let format = image::ImageFormat::from_extension(s.trim_start_matches('.'))
+		.ok_or_else(|| format!("`{}` is not a known image format", s))?;
+
+	if !format.can_write() {
+		return Err(format!("{:?} can't be written", format));
+	}
+
+	Ok(format)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Flip {
+	Horizontal,
+	Vertical,
+}
+
+pub struct ConvertOptions {
+	pub format: image::ImageFormat,
+	pub out_dir: Option<PathBuf>,
+	pub quality: u8,
+	pub resize: Option<Resize>,
+	pub rotate: Option<u16>,
+	pub flip: Option<Flip>,
+	pub overwrite: bool,
+	pub decode: DecodeOptions, // From the config, before the transforms
+}
+
+// Encoders only take some color types, convert to the closest one
+fn fit_color(image: image::DynamicImage, format: image::ImageFormat) -> image::DynamicImage {
+	use image::DynamicImage::*;
+
+	let gray = matches!(
+		image,
+		ImageLuma8(_) | ImageLumaA8(_) | ImageLuma16(_) | ImageLumaA16(_)
+	);
+	let alpha = image.color().has_alpha();
+
+	match format {
+		// No alpha, no 16 bit
+		image::ImageFormat::Jpeg if gray => ImageLuma8(image.into_luma8()),
+		image::ImageFormat::Jpeg => ImageRgb8(image.into_rgb8()),
+		// 8 and 16 bit are fine, floats aren't
+		image::ImageFormat::Png | image::ImageFormat::Tiff => match image {
+			ImageRgb32F(_) | ImageRgba32F(_) => ImageRgba16(image.into_rgba16()),
+			_ => image,
+		},
+		image::ImageFormat::OpenExr => ImageRgba32F(image.into_rgba32f()),
+		image::ImageFormat::Farbfeld => ImageRgba16(image.into_rgba16()),
+		// Everything else is 8 bit
+		_ if alpha => ImageRgba8(image.into_rgba8()),
+		_ => ImageRgb8(image.into_rgb8()),
+	}
+}
+
+// Resize, rotate and flip in that order
+fn transform(mut image: image::DynamicImage, options: &ConvertOptions) -> image::DynamicImage {
+	let filter = image::imageops::FilterType::Lanczos3;
+
+	image = match options.resize {
+		Some(Resize::Fit(width, height)) => image.resize(width, height, filter),
+		Some(Resize::Percent(percent)) => {
+			let width = ((image.width() as f32 * percent / 100.0).round() as u32).max(1);
+			let height = ((image.height() as f32 * percent / 100.0).round() as u32).max(1);
+			image.resize_exact(width, height, filter)
+		}
+		None => image,
+	};
+
+	image = match options.rotate {
+		Some(90) => image.rotate90(),
+		Some(180) => image.rotate180(),
+		Some(270) => image.rotate270(),
+		_ => image,
+	};
+
+	match options.flip {
+		Some(Flip::Horizontal) => image.fliph(),
+		Some(Flip::Vertical) => image.flipv(),
+		None => image,
+	}
+}
+
+// Same name, new extension, next to the input unless `--out-dir` is given
+fn output_path(source: &ImageSource, options: &ConvertOptions) -> PathBuf {
+	let dir = match (&options.out_dir, source.path()) {
+		(Some(dir), _) => dir.clone(),
+		(None, Some(path)) => path
+			.parent()
+			.unwrap_or_else(|| Path::new("."))
+			.to_path_buf(),
+		(None, None) => PathBuf::from("."),
+	};
+
+	let mut name = match source.path() {
+		Some(path) => path.file_stem().unwrap_or_default().to_os_string(),
+		None => std::ffi::OsString::from(source.name()),
+	};
+	// Appended, with_extension would replace the last part of e.g. `logo.v2`
+	name.push(".");
+	name.push(options.format.extensions_str()[0]);
+
+	dir.join(name)
+}
+
+// Files that don't exist yet can't be canonicalized, their directory can
+fn resolved(path: &Path) -> PathBuf {
+	if let Ok(path) = path.canonicalize() {
+		return path;
+	}
+
+	match (path.parent(), path.file_name()) {
+		(Some(dir), Some(name)) => {
+			let dir = if dir.as_os_str().is_empty() {
+				Path::new(".")
+			} else {
+				dir
+			};
+			dir.canonicalize()
+				.map(|dir| dir.join(name))
+				.unwrap_or_else(|_| path.to_path_buf())
+		}
+		_ => path.to_path_buf(),
+	}
+}
+
+// Two inputs with the same stem, e.g. `a/x.jpg` and `b/x.jpg` with `--out-dir`
+fn find_collision(sources: &[ImageSource], options: &ConvertOptions) -> Option<String> {
+	let mut outputs = std::collections::HashMap::new();
+
+	for source in sources {
+		let output = output_path(source, options);
+		if let Some(first) = outputs.insert(resolved(&output), source) {
+			return Some(format!(
+				"`{}` and `{}` would both be written to `{}`",
+				first.path().unwrap_or_else(|| Path::new("-")).display(),
+				source.path().unwrap_or_else(|| Path::new("-")).display(),
+				output.display()
+			));
+		}
+	}
+
+	None
+}
+
+// Errors are (exit code, message)
+fn convert_one(source: &ImageSource, options: &ConvertOptions) -> Result<PathBuf, (i32, String)> {
+	let output = output_path(source, options);
+	let output_error = |e: String| {
+		(
+			cli::ExitCodes::OUTPUT,
+			format!("Can't convert `{}`: {}", source.name(), e),
+		)
+	};
+
+	if source.path().map(resolved) == Some(resolved(&output)) {
+		return Err(output_error("output would replace the input".to_string()));
+	}
+	if output.exists() && !options.overwrite {
+		return Err(output_error(format!(
+			"`{}` already exists, use --overwrite",
+			output.display()
+		)));
+	}
+
+	let image =
+		UiUtils::decode(source, &options.decode).map_err(|e| (e.exit_code(), e.to_string()))?;
+	let image = fit_color(transform(image, options), options.format);
+
+	let output_format = match options.format {
+		image::ImageFormat::Jpeg => image::ImageOutputFormat::Jpeg(options.quality),
+		format => format.into(),
+	};
+
+	// Write to memory first so a failed encode doesn't leave half a file
+	let mut bytes = std::io::Cursor::new(Vec::new());
+	image
+		.write_to(&mut bytes, output_format)
+		.map_err(|e| output_error(e.to_string()))?;
+	std::fs::write(&output, bytes.into_inner()).map_err(|e| output_error(e.to_string()))?;
+
+	Ok(output)
+}
+
+pub fn convert(images: &[String], options: &ConvertOptions) -> i32 {
+	let playlist = match playlist::Playlist::from_paths(images) {
+		Ok(playlist) => playlist,
+		Err(e) => {
+			eprintln!("{}", e);
+			return cli::ExitCodes::NO_INPUT;
+		}
+	};
+
+	// Checked before anything is written
+	if let Some(e) = find_collision(playlist.sources(), options) {
+		eprintln!("Can't convert: {}", e);
+		return cli::ExitCodes::OUTPUT;
+	}
+
+	if let Some(dir) = &options.out_dir {
+		if let Err(e) = std::fs::create_dir_all(dir) {
+			eprintln!("Can't create `{}`: {}", dir.display(), e);
+			return cli::ExitCodes::OUTPUT;
+		}
+	}
+
+	// Keep going after a failure, report the last error code
+	let mut code = cli::ExitCodes::SUCCESS;
+	for source in playlist.sources() {
+		match convert_one(source, options) {
+			Ok(output) => println!("{} -> {}", source.name(), output.display()),
+			Err((error_code, e)) => {
+				code = error_code;
+				eprintln!("{}", e);
+			}
+		}
+	}
+
+	code
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn options(format: image::ImageFormat, out_dir: Option<&str>) -> ConvertOptions {
+		ConvertOptions {
+			format,
+			out_dir: out_dir.map(PathBuf::from),
+			quality: 90,
+			resize: None,
+			rotate: None,
+			flip: None,
+			overwrite: false,
+			decode: DecodeOptions {
+				auto_orient: false,
+				color: None,
+			},
+		}
+	}
+
+	fn file(path: &str) -> ImageSource {
+		ImageSource::File(PathBuf::from(path))
+	}
+
+	#[test]
+	fn resize() {
+		assert_eq!(parse_resize("640x480"), Ok(Resize::Fit(640, 480)));
+		assert_eq!(parse_resize("50%"), Ok(Resize::Percent(50.0)));
+		assert_eq!(parse_resize("12.5%"), Ok(Resize::Percent(12.5)));
+		assert!(parse_resize("0%").is_err());
+		assert!(parse_resize("-5%").is_err());
+		assert!(parse_resize("0x480").is_err());
+		assert!(parse_resize("640").is_err());
+		assert!(parse_resize("ax480").is_err());
+	}
+
+	#[test]
+	fn format() {
+		assert_eq!(parse_format("png"), Ok(image::ImageFormat::Png));
+		assert_eq!(parse_format(".jpg"), Ok(image::ImageFormat::Jpeg));
+		assert_eq!(parse_format("JPEG"), Ok(image::ImageFormat::Jpeg));
+		assert!(parse_format("foo").is_err());
+	}
+
+	#[test]
+	fn output_next_to_input() {
+		let png = options(image::ImageFormat::Png, None);
+		assert_eq!(output_path(&file("a/x.jpg"), &png), Path::new("a/x.png"));
+		assert_eq!(output_path(&file("x.jpg"), &png), Path::new("x.png"));
+		// Only the last extension is replaced
+		assert_eq!(
+			output_path(&file("a/logo.v2.jpg"), &png),
+			Path::new("a/logo.v2.png")
+		);
+		assert_eq!(
+			output_path(&file("a/logo.v2"), &png),
+			Path::new("a/logo.png")
+		);
+
+		let memory = ImageSource::Memory("stdin".to_string(), Default::default());
+		assert_eq!(output_path(&memory, &png), Path::new("./stdin.png"));
+	}
+
+	#[test]
+	fn output_in_out_dir() {
+		let jpg = options(image::ImageFormat::Jpeg, Some("out"));
+		assert_eq!(output_path(&file("a/x.png"), &jpg), Path::new("out/x.jpg"));
+	}
+
+	#[test]
+	fn colliding_outputs() {
+		let png = options(image::ImageFormat::Png, Some("out"));
+		assert!(find_collision(&[file("a/x.jpg"), file("b/y.jpg")], &png).is_none());
+		assert!(find_collision(&[file("a/x.jpg"), file("b/x.jpg")], &png).is_some());
+
+		// Next to the input they don't collide
+		let png = options(image::ImageFormat::Png, None);
+		assert!(find_collision(&[file("a/x.jpg"), file("b/x.jpg")], &png).is_none());
+		assert!(find_collision(&[file("a/x.jpg"), file("a/x.gif")], &png).is_some());
+	}
+
+	#[test]
+	fn output_replacing_input() {
+		// Same file, spelled differently
+		let png = options(image::ImageFormat::Png, Some(env!("CARGO_MANIFEST_DIR")));
+		let result = convert_one(
+			&file("src/../icon.png"),
+			&ConvertOptions {
+				overwrite: true,
+				..png
+			},
+		);
+		assert!(matches!(result, Err((cli::ExitCodes::OUTPUT, _))));
+	}
+}
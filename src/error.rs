@@ -0,0 +1,117 @@
+// error.rs
+// Everything that can go wrong while loading an image
+// Callers decide if they skip, retry or show a message
+// Every variant starts with the name of the image it's about
+
+use crate::cli;
+use crate::utils::ImageSource;
+
+#[derive(Debug)]
+pub enum LoadError {
+	// File doesn't exist
+	NotFound(String),
+	// File exists but we can't read it
+	PermissionDenied(String),
+	// Any other IO problem, e.g. stdin closed
+	Io(String, std::io::Error),
+	// Not an image, or a format r-liv can't decode
+	UnsupportedFormat(String, String),
+	// Known format but broken or cut off data
+	Decode(String, String),
+	// Bigger than the GPU can hold or draw into, e.g. a huge `render --size`
+	// The window splits big images into tiles instead, see texture.rs
+	TextureTooLarge {
+		name: String,
+		width: u32,
+		height: u32,
+		max: (u32, u32),
+	},
+	// Not enough free video memory for the texture
+	OutOfVram {
+		name: String,
+		needed: usize, // In bytes
+		free: usize,   // In bytes
+	},
+	// The driver refused the texture for another reason
+	Texture(String, glium::texture::TextureCreationError),
+}
+
+impl LoadError {
+	pub fn from_io(source: &ImageSource, error: std::io::Error) -> LoadError {
+		let name = source.name();
+
+		match error.kind() {
+			std::io::ErrorKind::NotFound => LoadError::NotFound(name),
+			std::io::ErrorKind::PermissionDenied => LoadError::PermissionDenied(name),
+			_ => LoadError::Io(name, error),
+		}
+	}
+
+	pub fn from_image(source: &ImageSource, error: image::ImageError) -> LoadError {
+		let name = source.name();
+
+		match error {
+			// Decoders read from memory, so these mean the data is cut off or garbage
+			image::ImageError::IoError(e)
+				if matches!(
+					e.kind(),
+					std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::InvalidData
+				) =>
+			{
+				LoadError::Decode(name, e.to_string())
+			}
+			image::ImageError::IoError(e) => LoadError::from_io(source, e),
+			image::ImageError::Unsupported(e) => LoadError::UnsupportedFormat(name, e.to_string()),
+			e => LoadError::Decode(name, e.to_string()),
+		}
+	}
+
+	// Exit code for headless commands
+	pub fn exit_code(&self) -> i32 {
+		match self {
+			LoadError::NotFound(_) | LoadError::PermissionDenied(_) | LoadError::Io(..) => {
+				cli::ExitCodes::NO_INPUT
+			}
+			LoadError::UnsupportedFormat(..) | LoadError::Decode(..) => cli::ExitCodes::DECODE,
+			LoadError::TextureTooLarge { .. }
+			| LoadError::OutOfVram { .. }
+			| LoadError::Texture(..) => cli::ExitCodes::OPENGL,
+		}
+	}
+}
+
+impl std::fmt::Display for LoadError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			LoadError::NotFound(name) => write!(f, "`{}` doesn't exist", name),
+			LoadError::PermissionDenied(name) => write!(f, "No permission to read `{}`", name),
+			LoadError::Io(name, e) => write!(f, "Can't read `{}`: {}", name, e),
+			LoadError::UnsupportedFormat(name, e) => {
+				write!(f, "`{}` is in an unsupported format: {}", name, e)
+			}
+			LoadError::Decode(name, e) => write!(f, "`{}` is broken: {}", name, e),
+			LoadError::TextureTooLarge {
+				name,
+				width,
+				height,
+				max,
+			} => write!(
+				f,
+				"`{}` needs a {}x{} texture but the GPU only supports up to {}x{}",
+				name, width, height, max.0, max.1
+			),
+			LoadError::OutOfVram { name, needed, free } => write!(
+				f,
+				"`{}` needs {}MB of video memory but only {}MB is free",
+				name,
+				needed / 1_000_000,
+				free / 1_000_000
+			),
+			LoadError::Texture(name, e) => {
+				write!(f, "Can't create a texture for `{}`: {}", name, e)
+			}
+		}
+	}
+}
+
+impl std::error::Error for LoadError {}
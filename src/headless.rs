@@ -0,0 +1,435 @@
+// headless.rs
+// OpenGL without a window, used by `r-liv render`
+// On Linux this is EGL on Mesa's surfaceless platform,
+// so it works on build servers without a display or a GPU (llvmpipe)
+
+extern crate glium;
+
+use crate::animation;
+use crate::cli;
+use crate::error::LoadError;
+use crate::playlist;
+use crate::settings;
+use crate::utils::{DecodeOptions, ImageSource, UiUtils};
+use crate::view;
+
+use glium::CapabilitiesSource;
+use std::rc::Rc;
+
+#[cfg(target_os = "linux")]
+mod egl {
+	extern crate khronos_egl;
+
+	use khronos_egl as egl;
+
+	// EGL_PLATFORM_SURFACELESS_MESA
+	const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+
+	pub struct EglBackend {
+		egl: egl::DynamicInstance<egl::EGL1_5>,
+		display: egl::Display,
+		context: egl::Context,
+	}
+
+	impl EglBackend {
+		pub fn new() -> Result<EglBackend, String> {
+			let egl = unsafe { egl::DynamicInstance::<egl::EGL1_5>::load_required() }
+				.map_err(|e| format!("Can't load libEGL: {}", e))?;
+
+			let display = egl
+				.get_platform_display(
+					PLATFORM_SURFACELESS_MESA,
+					egl::DEFAULT_DISPLAY,
+					&[egl::ATTRIB_NONE],
+				)
+				.map_err(|e| format!("No surfaceless EGL display: {}", e))?;
+			egl.initialize(display)
+				.map_err(|e| format!("Can't initialize EGL: {}", e))?;
+
+			let config = egl
+				.choose_first_config(
+					display,
+					&[
+						// Defaults to windows otherwise
+						egl::SURFACE_TYPE,
+						egl::PBUFFER_BIT,
+						egl::RENDERABLE_TYPE,
+						egl::OPENGL_BIT,
+						egl::RED_SIZE,
+						8,
+						egl::GREEN_SIZE,
+						8,
+						egl::BLUE_SIZE,
+						8,
+						egl::ALPHA_SIZE,
+						8,
+						egl::NONE,
+					],
+				)
+				.map_err(|e| format!("Can't choose an EGL config: {}", e))?
+				.ok_or_else(|| "No EGL config supports OpenGL".to_string())?;
+
+			egl.bind_api(egl::OPENGL_API)
+				.map_err(|e| format!("EGL doesn't support OpenGL: {}", e))?;
+			let context = egl
+				.create_context(display, config, None, &[egl::NONE])
+				.map_err(|e| format!("Can't create an EGL context: {}", e))?;
+
+			Ok(EglBackend {
+				egl,
+				display,
+				context,
+			})
+		}
+	}
+
+	unsafe impl glium::backend::Backend for EglBackend {
+		// Nothing to swap, everything is drawn into textures
+		fn swap_buffers(&self) -> Result<(), glium::SwapBuffersError> {
+			Ok(())
+		}
+
+		unsafe fn get_proc_address(&self, symbol: &str) -> *const std::ffi::c_void {
+			match self.egl.get_proc_address(symbol) {
+				Some(function) => function as *const std::ffi::c_void,
+				None => std::ptr::null(),
+			}
+		}
+
+		// There is no default framebuffer
+		fn get_framebuffer_dimensions(&self) -> (u32, u32) {
+			(0, 0)
+		}
+
+		fn is_current(&self) -> bool {
+			self.egl.get_current_context() == Some(self.context)
+		}
+
+		unsafe fn make_current(&self) {
+			self.egl
+				.make_current(self.display, None, None, Some(self.context))
+				.unwrap();
+		}
+	}
+
+	impl Drop for EglBackend {
+		fn drop(&mut self) {
+			self.egl.destroy_context(self.display, self.context).ok();
+			self.egl.terminate(self.display).ok();
+		}
+	}
+}
+
+// glium context that doesn't need a window
+#[cfg(target_os = "linux")]
+pub fn create_context() -> Result<Rc<glium::backend::Context>, String> {
+	let backend = egl::EglBackend::new()?;
+
+	unsafe { glium::backend::Context::new(backend, true, Default::default()) }
+		.map_err(|e| format!("OpenGL is too old: {:?}", e))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn create_context() -> Result<Rc<glium::backend::Context>, String> {
+	Err("Rendering without a window is only supported on Linux".to_string())
+}
+
+// `--size WxH`
+pub fn parse_size(s: &str) -> Result<(u32, u32), String> {
+	let (width, height) = s
+		.split_once('x')
+		.ok_or_else(|| format!("`{}` is not WIDTHxHEIGHT", s))?;
+	let width: u32 = width
+		.parse()
+		.map_err(|_| format!("`{}` is not a width", width))?;
+	let height: u32 = height
+		.parse()
+		.map_err(|_| format!("`{}` is not a height", height))?;
+	if width == 0 || height == 0 {
+		return Err("size must not be zero".to_string());
+	}
+
+	Ok((width, height))
+}
+
+// `--offset X,Y`
+pub fn parse_offset(s: &str) -> Result<(f32, f32), String> {
+	let (x, y) = s
+		.split_once(',')
+		.ok_or_else(|| format!("`{}` is not X,Y", s))?;
+	let x: f32 = x
+		.trim()
+		.parse()
+		.map_err(|_| format!("`{}` is not a number", x))?;
+	let y: f32 = y
+		.trim()
+		.parse()
+		.map_err(|_| format!("`{}` is not a number", y))?;
+
+	Ok((x, y))
+}
+
+// Biggest texture that can also be drawn into whole
+fn max_target_size(context: &Rc<glium::backend::Context>) -> (u32, u32) {
+	let capabilities = context.get_capabilities();
+	let max = capabilities.max_texture_size.max(1) as u32;
+	let (width, height) = capabilities.max_viewport_dims;
+
+	(max.min(width.max(1) as u32), max.min(height.max(1) as u32))
+}
+
+// Shrunk to fit into max, keeping the aspect ratio
+fn fit_within(size: (u32, u32), max: (u32, u32)) -> (u32, u32) {
+	if size.0 <= max.0 && size.1 <= max.1 {
+		return size;
+	}
+
+	let scale = (max.0 as f64 / size.0 as f64).min(max.1 as f64 / size.1 as f64);
+	(
+		((size.0 as f64 * scale).round() as u32).clamp(1, max.0),
+		((size.1 as f64 * scale).round() as u32).clamp(1, max.1),
+	)
+}
+
+pub struct RenderOptions {
+	pub size: Option<(u32, u32)>, // Defaults to the image size
+	pub view: view::View,
+	pub output: std::path::PathBuf,
+	pub background: [f32; 4],       // From the config
+	pub decode: DecodeOptions,      // From the config
+	pub hdr: settings::HdrSettings, // From the config
+}
+
+// SVGs are rasterized again for the size they end up at, like in the window
+// Everything else, or SVGs that don't need more pixels, stays as it is
+fn sharpen(
+	context: &Rc<glium::backend::Context>,
+	source: &ImageSource,
+	image: &mut animation::Animation,
+	options: &RenderOptions,
+	size: (u32, u32),
+) -> Result<(), LoadError> {
+	let vector = match &image.vector {
+		Some(vector) => vector,
+		None => return Ok(()),
+	};
+
+	let (width, height) = image.texture().dimensions();
+	let texel_size = options
+		.view
+		.texel_size((width, height), (size.0 as f32, size.1 as f32));
+	let scale = vector.scale_for(texel_size);
+	if scale <= image.raster_scale {
+		return Ok(());
+	}
+
+	if let Some(pixels) = UiUtils::rasterize(vector, scale, &options.decode) {
+		let mut texture = UiUtils::upload_texture(context, source, pixels)?;
+		texture.stretch(width, height);
+		image.replace_texture(texture);
+	}
+
+	Ok(())
+}
+
+// `r-liv render`
+// Draws exactly like the window would at that size, zoom and pan
+pub fn render(image: &str, options: &RenderOptions) -> i32 {
+	let playlist = match playlist::Playlist::from_paths(&[image.to_string()]) {
+		Ok(playlist) => playlist,
+		Err(e) => {
+			eprintln!("{}", e);
+			return cli::ExitCodes::NO_INPUT;
+		}
+	};
+
+	let context = match create_context() {
+		Ok(context) => context,
+		Err(e) => {
+			eprintln!("{}", e);
+			return cli::ExitCodes::OPENGL;
+		}
+	};
+
+	let source = playlist.current();
+	let mut animation = match UiUtils::decode_frames(source, 0, &options.decode)
+		.and_then(|decoded| UiUtils::upload_frames(&context, source, decoded))
+	{
+		Ok(animation) => animation,
+		Err(e) => {
+			eprintln!("{}", e);
+			return e.exit_code();
+		}
+	};
+
+	// Huge images are drawn smaller, a huge --size can't be drawn at all
+	let max = max_target_size(&context);
+	let (width, height) = match options.size {
+		Some((width, height)) if width > max.0 || height > max.1 => {
+			let e = LoadError::TextureTooLarge {
+				name: source.name(),
+				width,
+				height,
+				max,
+			};
+			eprintln!("{}", e);
+			return e.exit_code();
+		}
+		Some(size) => size,
+		None => fit_within(animation.texture().dimensions(), max),
+	};
+
+	if let Err(e) = sharpen(&context, source, &mut animation, options, (width, height)) {
+		eprintln!("{}", e);
+		return e.exit_code();
+	}
+	// First frame of animations
+	let texture = animation.texture();
+
+	// Draw into a texture instead of a window
+	let target = match glium::texture::SrgbTexture2d::empty_with_format(
+		&context,
+		glium::texture::SrgbFormat::U8U8U8U8,
+		glium::texture::MipmapsOption::NoMipmap,
+		width,
+		height,
+	) {
+		Ok(target) => target,
+		Err(e) => {
+			eprintln!("Can't create a {}x{} render target: {}", width, height, e);
+			return cli::ExitCodes::OPENGL;
+		}
+	};
+	let mut framebuffer = match glium::framebuffer::SimpleFrameBuffer::new(&context, &target) {
+		Ok(framebuffer) => framebuffer,
+		Err(e) => {
+			eprintln!("Can't create a framebuffer: {:?}", e);
+			return cli::ExitCodes::OPENGL;
+		}
+	};
+
+	let [r, g, b, a] = options.background;
+	glium::Surface::clear_color(&mut framebuffer, r, g, b, a);
+	if let Err(e) = options
+		.view
+		.draw(&context, &mut framebuffer, texture, &options.hdr)
+	{
+		eprintln!("{}", e);
+		return cli::ExitCodes::OPENGL;
+	}
+
+	// OpenGL is bottom to top
+	let pixels: glium::texture::RawImage2d<u8> = target.read();
+	let result = image::RgbaImage::from_raw(width, height, pixels.data.into_owned()).unwrap();
+	let result = image::imageops::flip_vertical(&result);
+
+	if let Err(e) = result.save(&options.output) {
+		eprintln!("Can't save `{}`: {}", options.output.display(), e);
+		return cli::ExitCodes::OUTPUT;
+	}
+
+	cli::ExitCodes::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sizes() {
+		assert_eq!(parse_size("640x480"), Ok((640, 480)));
+		assert!(parse_size("640").is_err());
+		assert!(parse_size("0x480").is_err());
+		assert!(parse_size("-1x480").is_err());
+	}
+
+	#[test]
+	fn offsets() {
+		assert_eq!(parse_offset("10, -2.5"), Ok((10.0, -2.5)));
+		assert!(parse_offset("10").is_err());
+		assert!(parse_offset("a,b").is_err());
+	}
+
+	#[test]
+	fn fit_into_limits() {
+		assert_eq!(fit_within((800, 600), (8192, 8192)), (800, 600));
+		assert_eq!(fit_within((20000, 10000), (8192, 8192)), (8192, 4096));
+		assert_eq!(fit_within((100, 100000), (16384, 8192)), (8, 8192));
+		assert_eq!(fit_within((1, 100000), (8192, 8192)), (1, 8192));
+	}
+
+	// `R_LIV_BLESS=1 cargo test golden` writes new references
+	#[test]
+	fn golden_renders() {
+		// Nothing to compare against without OpenGL
+		if let Err(e) = create_context() {
+			eprintln!("Skipping golden renders: {}", e);
+			return;
+		}
+
+		let golden = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+		let out = std::env::temp_dir().join(format!("r-liv-golden-{}", std::process::id()));
+		std::fs::create_dir_all(&out).unwrap();
+		let bless = std::env::var_os("R_LIV_BLESS").is_some();
+
+		let cases = [
+			("contain", cli::FitMode::Contain, 1.0, (0.0, 0.0), 0),
+			("cover", cli::FitMode::Cover, 1.0, (0.0, 0.0), 0),
+			("width_rotated", cli::FitMode::Width, 1.0, (0.0, 0.0), 90),
+			("height_rotated", cli::FitMode::Height, 1.0, (0.0, 0.0), 270),
+			("original", cli::FitMode::Original, 3.0, (0.0, 0.0), 180),
+			(
+				"zoomed_panned",
+				cli::FitMode::Contain,
+				2.5,
+				(12.0, -7.0),
+				90,
+			),
+		];
+
+		// One after another, every render makes and drops its own EGL display
+		for (name, fit, zoom, offset, rotation) in cases {
+			let file = format!("{}.png", name);
+			let options = RenderOptions {
+				size: Some((64, 48)),
+				view: view::View {
+					rotation,
+					..view::View::new(fit, zoom, offset)
+				},
+				output: if bless {
+					golden.join(&file)
+				} else {
+					out.join(&file)
+				},
+				background: [0.1, 0.2, 0.3, 1.0],
+				decode: DecodeOptions {
+					auto_orient: false,
+					color: None,
+				},
+				hdr: settings::HdrSettings::default(),
+			};
+			let icon = concat!(env!("CARGO_MANIFEST_DIR"), "/icon.png");
+			assert_eq!(render(icon, &options), cli::ExitCodes::SUCCESS, "{}", name);
+
+			let actual = image::open(&options.output).unwrap().into_rgba8();
+			let expected = image::open(golden.join(&file)).unwrap().into_rgba8();
+			assert_eq!(actual.dimensions(), expected.dimensions(), "{}", name);
+
+			// Drivers round edges a little differently
+			let off = actual
+				.pixels()
+				.zip(expected.pixels())
+				.filter(|(a, e)| a.0.iter().zip(e.0.iter()).any(|(a, e)| a.abs_diff(*e) > 2))
+				.count();
+			assert!(
+				off <= (actual.width() * actual.height()) as usize / 100,
+				"{}: {} pixels differ from tests/golden/{}",
+				name,
+				off,
+				file
+			);
+		}
+
+		std::fs::remove_dir_all(&out).ok();
+	}
+}
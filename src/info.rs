@@ -0,0 +1,413 @@
+// info.rs
+// Image metadata, used by `r-liv info`
+// Never touches OpenGL so it works on machines without a display
+
+extern crate exif;
+extern crate serde_json;
+
+use crate::cli;
+use crate::color;
+use crate::error::LoadError;
+use crate::pages;
+use crate::playlist;
+use crate::raw;
+use crate::svg;
+use crate::utils::{ImageSource, UiUtils};
+
+use std::io::Write;
+
+#[derive(serde::Serialize)]
+pub struct ImageInfo {
+	pub name: String,
+	pub path: Option<String>,
+	pub format: Option<String>,
+	pub width: u32,
+	pub height: u32,
+	pub color_type: String,
+	pub channels: u8,
+	pub bit_depth: u16, // Per channel
+	pub file_size: u64, // In bytes
+	pub frames: usize,
+	pub pages: usize,            // Multi-page TIFF and ICO, see pages.rs
+	pub profile: Option<String>, // Name of the embedded ICC profile
+	pub exif: Vec<ExifField>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExifField {
+	pub tag: String,
+	pub value: String,
+	pub thumbnail: bool, // Field describes the embedded thumbnail, not the image
+}
+
+impl ImageInfo {
+	// Decodes the whole image with UiUtils::decode_bytes,
+	// so everything matches what the viewer shows
+	// auto_orient swaps width and height for images that are turned by 90 degrees
+	pub fn read(source: &ImageSource, auto_orient: bool) -> Result<ImageInfo, LoadError> {
+		let bytes = source.bytes().map_err(|e| LoadError::from_io(source, e))?;
+		let image = UiUtils::decode_bytes(source, &bytes)?;
+		let format = UiUtils::guess_format(source, &bytes);
+		let color = image.color();
+		// The TIFF inside isn't what's shown, the preview is
+		let raw = raw::kind(source, &bytes);
+		let (width, height) = match auto_orient.then(|| UiUtils::orientation(&bytes)) {
+			Some(5..=8) => (image.height(), image.width()),
+			_ => (image.width(), image.height()),
+		};
+
+		Ok(ImageInfo {
+			name: source.name(),
+			path: source.path().map(|p| p.display().to_string()),
+			format: raw
+				.map(|kind| kind.to_string())
+				.or_else(|| format.map(|f| format!("{:?}", f)))
+				.or_else(|| svg::is_svg(source, &bytes).then(|| "Svg".to_string())),
+			width,
+			height,
+			color_type: format!("{:?}", color),
+			channels: color.channel_count(),
+			bit_depth: color.bits_per_pixel() / color.channel_count() as u16,
+			file_size: bytes.len() as u64,
+			frames: ImageInfo::count_frames(format, &bytes),
+			pages: if raw.is_some() {
+				1
+			} else {
+				pages::count(format, &bytes)
+			},
+			profile: color::icc_profile(format, &bytes, 0)
+				.filter(|_| raw.is_none())
+				.map(|icc| color::name(&icc)),
+			exif: ImageInfo::read_exif(&bytes),
+		})
+	}
+
+	// Animated formats, everything else is 1
+	// Read from the file structure, decoding every frame would take as long as opening it
+	fn count_frames(format: Option<image::ImageFormat>, bytes: &[u8]) -> usize {
+		let frames = match format {
+			Some(image::ImageFormat::Gif) => gif_frames(bytes),
+			Some(image::ImageFormat::Png) => apng_frames(bytes),
+			Some(image::ImageFormat::WebP) => webp_frames(bytes),
+			_ => None,
+		};
+
+		frames.filter(|&frames| frames > 0).unwrap_or(1)
+	}
+
+	// Images without EXIF just have none
+	fn read_exif(bytes: &[u8]) -> Vec<ExifField> {
+		let exif = match exif::Reader::new().read_from_container(&mut std::io::Cursor::new(bytes)) {
+			Ok(exif) => exif,
+			Err(_) => return Vec::new(),
+		};
+
+		exif.fields()
+			.map(|field| ExifField {
+				tag: field.tag.to_string(),
+				value: field.display_value().with_unit(&exif).to_string(),
+				thumbnail: field.ifd_num == exif::In::THUMBNAIL,
+			})
+			.collect()
+	}
+
+	fn print_text(&self, out: &mut impl Write) -> std::io::Result<()> {
+		writeln!(out, "{}", self.path.as_deref().unwrap_or(&self.name))?;
+		writeln!(
+			out,
+			"  Format:     {}",
+			self.format.as_deref().unwrap_or("unknown")
+		)?;
+		writeln!(out, "  Dimensions: {}x{}", self.width, self.height)?;
+		writeln!(out, "  Color type: {}", self.color_type)?;
+		writeln!(out, "  Channels:   {}", self.channels)?;
+		writeln!(out, "  Bit depth:  {}", self.bit_depth)?;
+		writeln!(out, "  File size:  {} bytes", self.file_size)?;
+		writeln!(out, "  Frames:     {}", self.frames)?;
+		writeln!(out, "  Pages:      {}", self.pages)?;
+		writeln!(
+			out,
+			"  Profile:    {}",
+			self.profile.as_deref().unwrap_or("none")
+		)?;
+
+		if !self.exif.is_empty() {
+			writeln!(out, "  EXIF:")?;
+			for field in &self.exif {
+				if field.thumbnail {
+					writeln!(out, "    {} (thumbnail): {}", field.tag, field.value)?;
+				} else {
+					writeln!(out, "    {}: {}", field.tag, field.value)?;
+				}
+			}
+		}
+
+		Ok(())
+	}
+}
+
+// Image descriptors of a GIF, skipping over the compressed data
+fn gif_frames(bytes: &[u8]) -> Option<usize> {
+	// Sub-blocks, each starts with its size, 0 ends them
+	let skip_blocks = |mut position: usize| -> Option<usize> {
+		loop {
+			let size = *bytes.get(position)? as usize;
+			position += 1 + size;
+			if size == 0 {
+				return Some(position);
+			}
+		}
+	};
+	// Color tables are 3 * 2^(N+1) bytes
+	let table = |flags: u8| {
+		if flags & 0x80 != 0 {
+			3 << ((flags & 0x07) + 1)
+		} else {
+			0
+		}
+	};
+
+	// Header and logical screen descriptor
+	let mut position = 13 + table(*bytes.get(10)?);
+	let mut frames = 0;
+	loop {
+		match *bytes.get(position)? {
+			// Extension, label then blocks
+			0x21 => position = skip_blocks(position + 2)?,
+			// Image descriptor, LZW code size then blocks
+			0x2C => {
+				frames += 1;
+				position = skip_blocks(position + 10 + table(*bytes.get(position + 9)?) + 1)?;
+			}
+			// Trailer
+			0x3B => return Some(frames),
+			_ => return None,
+		}
+	}
+}
+
+// num_frames of the acTL chunk, None for still PNGs
+fn apng_frames(bytes: &[u8]) -> Option<usize> {
+	let mut position = 8;
+	loop {
+		let length = u32::from_be_bytes(bytes.get(position..position + 4)?.try_into().ok()?);
+		match bytes.get(position + 4..position + 8)? {
+			b"acTL" => {
+				let data = bytes.get(position + 8..position + 12)?;
+				return Some(u32::from_be_bytes(data.try_into().ok()?) as usize);
+			}
+			// acTL has to come before the image data
+			b"IDAT" | b"IEND" => return None,
+			_ => position = position.checked_add(12 + length as usize)?,
+		}
+	}
+}
+
+// ANMF chunks of an animated WebP, None for still ones
+fn webp_frames(bytes: &[u8]) -> Option<usize> {
+	let mut position = 12;
+	let mut frames = 0;
+	while let Some(header) = bytes.get(position..position + 8) {
+		if &header[0..4] == b"ANMF" {
+			frames += 1;
+		}
+		// Padded to an even size
+		let size = u32::from_le_bytes(header[4..8].try_into().ok()?) as usize;
+		position = position.checked_add(8 + size + size % 2)?;
+	}
+
+	(frames > 0).then_some(frames)
+}
+
+// A closed pipe (`r-liv info *.jpg | head`) is a normal way to stop reading
+fn write_failed(e: std::io::Error, code: i32) -> i32 {
+	if e.kind() == std::io::ErrorKind::BrokenPipe {
+		return code;
+	}
+
+	eprintln!("Can't write to stdout: {}", e);
+	cli::ExitCodes::OUTPUT
+}
+
+// `r-liv info`
+pub fn print_info(images: &[String], json: bool, auto_orient: bool) -> i32 {
+	write_info(images, json, auto_orient, &mut std::io::stdout().lock())
+}
+
+fn write_info(images: &[String], json: bool, auto_orient: bool, out: &mut impl Write) -> i32 {
+	let playlist = match playlist::Playlist::from_paths(images) {
+		Ok(playlist) => playlist,
+		Err(e) => {
+			eprintln!("{}", e);
+			return cli::ExitCodes::NO_INPUT;
+		}
+	};
+
+	let mut code = cli::ExitCodes::SUCCESS;
+	let mut entries = Vec::new();
+
+	for source in playlist.sources() {
+		match ImageInfo::read(source, auto_orient) {
+			Ok(info) if json => entries.push(serde_json::to_value(&info).unwrap()),
+			Ok(info) => {
+				if let Err(e) = info.print_text(out) {
+					return write_failed(e, code);
+				}
+			}
+			Err(e) => {
+				code = e.exit_code();
+				eprintln!("{}", e);
+
+				if json {
+					entries.push(serde_json::json!({
+						"name": source.name(),
+						"path": source.path().map(|p| p.display().to_string()),
+						"error": e.to_string(),
+					}));
+				}
+			}
+		}
+	}
+
+	if json {
+		if let Err(e) = writeln!(out, "{}", serde_json::to_string_pretty(&entries).unwrap()) {
+			return write_failed(e, code);
+		}
+	}
+
+	code
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::utils::tests::oriented_jpeg;
+
+	fn icon() -> String {
+		concat!(env!("CARGO_MANIFEST_DIR"), "/icon.png").to_string()
+	}
+
+	fn output(images: &[String], json: bool, auto_orient: bool) -> String {
+		let mut out = Vec::new();
+		assert_eq!(
+			write_info(images, json, auto_orient, &mut out),
+			cli::ExitCodes::SUCCESS
+		);
+		String::from_utf8(out).unwrap()
+	}
+
+	#[test]
+	fn text() {
+		let text = output(&[icon()], false, true);
+		assert!(text.starts_with(&icon()));
+		assert!(text.contains("  Format:     Png\n"));
+		assert!(text.contains("  Dimensions: 10x5\n"));
+		assert!(text.contains("  Color type: Rgba8\n"));
+		assert!(text.contains("  Frames:     1\n"));
+		assert!(text.contains("  Profile:    none\n"));
+	}
+
+	#[test]
+	fn json() {
+		let json: serde_json::Value = serde_json::from_str(&output(&[icon()], true, true)).unwrap();
+		let info = &json.as_array().unwrap()[0];
+		assert_eq!(info["name"], "icon.png");
+		assert_eq!(info["format"], "Png");
+		assert_eq!(
+			(info["width"].as_u64(), info["height"].as_u64()),
+			(Some(10), Some(5))
+		);
+		assert_eq!(info["channels"], 4);
+		assert_eq!(info["bit_depth"], 8);
+		assert_eq!(info["frames"], 1);
+		assert!(info["exif"].as_array().unwrap().is_empty());
+	}
+
+	#[test]
+	fn oriented_size() {
+		let path = std::env::temp_dir().join(format!("r-liv-info-{}.jpg", std::process::id()));
+		for (orientation, oriented) in [
+			(1, "10x5"),
+			(3, "10x5"),
+			(5, "5x10"),
+			(6, "5x10"),
+			(8, "5x10"),
+		] {
+			std::fs::write(&path, oriented_jpeg(orientation)).unwrap();
+			let images = [path.display().to_string()];
+
+			let text = output(&images, false, true);
+			assert!(
+				text.contains(&format!("Dimensions: {}\n", oriented)),
+				"{}",
+				orientation
+			);
+			assert!(text.contains("Orientation"));
+			// Stored size when the viewer doesn't turn images
+			assert!(output(&images, false, false).contains("Dimensions: 10x5\n"));
+		}
+		std::fs::remove_file(&path).ok();
+	}
+
+	#[test]
+	fn gif_frame_count() {
+		let mut gif = Vec::new();
+		{
+			let mut encoder = image::codecs::gif::GifEncoder::new(&mut gif);
+			let frames = (0..3).map(|i| {
+				image::Frame::new(image::RgbaImage::from_pixel(
+					4,
+					3,
+					image::Rgba([i * 80, 0, 0, 255]),
+				))
+			});
+			encoder.encode_frames(frames).unwrap();
+		}
+
+		assert_eq!(
+			ImageInfo::count_frames(Some(image::ImageFormat::Gif), &gif),
+			3
+		);
+		assert_eq!(gif_frames(&gif[..gif.len() - 1]), None);
+	}
+
+	#[test]
+	fn apng_frame_count() {
+		let chunk = |kind: &[u8], data: &[u8]| {
+			let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+			chunk.extend_from_slice(kind);
+			chunk.extend_from_slice(data);
+			chunk.extend_from_slice(&[0; 4]); // CRC isn't checked
+			chunk
+		};
+		let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+		png.extend(chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 6, 0, 0, 0]));
+		png.extend(chunk(b"acTL", &[0, 0, 0, 4, 0, 0, 0, 0]));
+		png.extend(chunk(b"IEND", &[]));
+
+		assert_eq!(apng_frames(&png), Some(4));
+		let still = std::fs::read(icon()).unwrap();
+		assert_eq!(apng_frames(&still), None);
+		assert_eq!(
+			ImageInfo::count_frames(Some(image::ImageFormat::Png), &still),
+			1
+		);
+	}
+
+	#[test]
+	fn webp_frame_count() {
+		let mut webp = b"RIFF\0\0\0\0WEBP".to_vec();
+		webp.extend_from_slice(b"VP8X\x0a\0\0\0");
+		webp.extend_from_slice(&[0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+		webp.extend_from_slice(b"ANIM\x06\0\0\0");
+		webp.extend_from_slice(&[0; 6]);
+		// Odd sizes are padded
+		for _ in 0..2 {
+			webp.extend_from_slice(b"ANMF\x03\0\0\0");
+			webp.extend_from_slice(&[0; 4]);
+		}
+
+		assert_eq!(webp_frames(&webp), Some(2));
+		assert_eq!(webp_frames(&webp[..30]), None);
+	}
+}
@@ -0,0 +1,339 @@
+// loader.rs
+// Decodes images on worker threads so the window never freezes
+// Only the upload to the GPU happens on the main thread
+
+use crate::error::LoadError;
+use crate::utils::{DecodeOptions, Decoded, ImageSource, UiUtils};
+
+// (playlist index, page), what the loader and the cache know images by
+pub type ImageKey = (usize, usize);
+
+// An image that is being decoded right now
+struct Job {
+	generation: u64,
+	key: ImageKey,
+	name: String,
+	started: std::time::Instant,
+}
+
+#[derive(Default)]
+pub struct Loader {
+	// Every job gets a new number, results of forgotten ones are stale
+	generation: u64,
+	jobs: Vec<Job>,
+	// Job of the image the user is waiting for, the others are prefetches
+	current: Option<u64>,
+}
+
+impl Loader {
+	// Decode the image the user wants to see
+	// Reuses a prefetch of the same image if one is running
+	// preview gets a quick low resolution version first if the image has one, see UiUtils::decode_preview
+	pub fn open<F, P>(
+		&mut self,
+		key: ImageKey,
+		source: &ImageSource,
+		options: &DecodeOptions,
+		done: F,
+		preview: Option<P>,
+	) where
+		F: FnOnce(u64, Result<Decoded, LoadError>) + Send + 'static,
+		P: FnOnce(u64, Decoded) + Send + 'static,
+	{
+		let running = self.is_decoding(key);
+		let generation = self.start(key, source, options, done);
+		self.current = Some(generation);
+
+		// A running prefetch is probably almost done already
+		if let Some(preview) = preview.filter(|_| !running) {
+			let source = source.clone();
+			let options = options.clone();
+			std::thread::Builder::new()
+				.name("preview".to_string())
+				.spawn(move || {
+					if let Some(decoded) = UiUtils::decode_preview(&source, &options) {
+						preview(generation, decoded);
+					}
+				})
+				.unwrap();
+		}
+	}
+
+	// Decode an image that might be wanted soon
+	pub fn prefetch<F>(
+		&mut self,
+		key: ImageKey,
+		source: &ImageSource,
+		options: &DecodeOptions,
+		done: F,
+	) where
+		F: FnOnce(u64, Result<Decoded, LoadError>) + Send + 'static,
+	{
+		self.start(key, source, options, done);
+	}
+
+	// Decode on a new thread, done gets the generation and the result
+	fn start<F>(
+		&mut self,
+		key: ImageKey,
+		source: &ImageSource,
+		options: &DecodeOptions,
+		done: F,
+	) -> u64
+	where
+		F: FnOnce(u64, Result<Decoded, LoadError>) + Send + 'static,
+	{
+		if let Some(job) = self.jobs.iter().find(|job| job.key == key) {
+			return job.generation;
+		}
+
+		self.generation += 1;
+		let generation = self.generation;
+
+		self.jobs.push(Job {
+			generation,
+			key,
+			name: source.name(),
+			started: std::time::Instant::now(),
+		});
+
+		let source = source.clone();
+		let options = options.clone();
+		std::thread::Builder::new()
+			.name("decoder".to_string())
+			.spawn(move || done(generation, UiUtils::decode_frames(&source, key.1, &options)))
+			.unwrap();
+
+		generation
+	}
+
+	// Image of a finished job and if the user is waiting for it
+	// None if the job was forgotten
+	pub fn finish(&mut self, generation: u64) -> Option<(ImageKey, bool)> {
+		let position = self
+			.jobs
+			.iter()
+			.position(|job| job.generation == generation)?;
+		let job = self.jobs.remove(position);
+
+		let current = self.current == Some(generation);
+		if current {
+			self.current = None;
+		}
+
+		Some((job.key, current))
+	}
+
+	// Stop waiting, returns the name of the image that was loading
+	// Decoders can't be interrupted, the result is just thrown away
+	pub fn cancel(&mut self) -> Option<String> {
+		let generation = self.current.take()?;
+		let position = self
+			.jobs
+			.iter()
+			.position(|job| job.generation == generation)?;
+
+		Some(self.jobs.remove(position).name)
+	}
+
+	// Stop waiting without forgetting the job, it can still end up in the cache
+	pub fn stop_waiting(&mut self) {
+		self.current = None;
+	}
+
+	// Throw away the results of every job, e.g. when they would decode differently now
+	pub fn forget(&mut self) {
+		self.jobs.clear();
+		self.current = None;
+	}
+
+	// Name and how long the image the user waits for has been loading
+	pub fn loading(&self) -> Option<(&str, std::time::Duration)> {
+		let generation = self.current?;
+		self.jobs
+			.iter()
+			.find(|job| job.generation == generation)
+			.map(|job| (job.name.as_str(), job.started.elapsed()))
+	}
+
+	// Previews of images the user stopped waiting for are thrown away
+	pub fn is_waiting_for(&self, generation: u64) -> bool {
+		self.current == Some(generation)
+	}
+
+	pub fn is_decoding(&self, key: ImageKey) -> bool {
+		self.jobs.iter().any(|job| job.key == key)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn source(name: &str) -> ImageSource {
+		ImageSource::Memory(
+			name.to_string(),
+			std::sync::Arc::new(b"not an image".to_vec()),
+		)
+	}
+
+	fn options() -> DecodeOptions {
+		DecodeOptions {
+			auto_orient: false,
+			color: None,
+		}
+	}
+
+	// Results are thrown away, the tests call finish themselves
+	fn open(loader: &mut Loader, key: ImageKey, name: &str) {
+		loader.open(
+			key,
+			&source(name),
+			&options(),
+			|_, _| {},
+			None::<fn(u64, Decoded)>,
+		);
+	}
+
+	fn prefetch(loader: &mut Loader, key: ImageKey, name: &str) {
+		loader.prefetch(key, &source(name), &options(), |_, _| {});
+	}
+
+	#[test]
+	fn done_gets_the_generation() {
+		let mut loader = Loader::default();
+		let (sender, receiver) = std::sync::mpsc::channel();
+		loader.open(
+			(0, 0),
+			&source("a"),
+			&options(),
+			move |generation, result: Result<Decoded, LoadError>| {
+				sender.send((generation, result.is_err())).unwrap();
+			},
+			None::<fn(u64, Decoded)>,
+		);
+
+		let (generation, failed) = receiver.recv().unwrap();
+		assert!(failed);
+		assert!(loader.is_waiting_for(generation));
+		assert_eq!(loader.finish(generation), Some(((0, 0), true)));
+		assert!(!loader.is_decoding((0, 0)));
+		assert!(loader.loading().is_none());
+	}
+
+	#[test]
+	fn open_makes_older_jobs_stale() {
+		let mut loader = Loader::default();
+		open(&mut loader, (0, 0), "a");
+		open(&mut loader, (1, 0), "b");
+		assert_eq!(loader.loading().map(|(name, _)| name), Some("b"));
+
+		// Still decoded for the cache, but nobody waits for it
+		assert!(!loader.is_waiting_for(1));
+		assert_eq!(loader.finish(1), Some(((0, 0), false)));
+		assert_eq!(loader.finish(2), Some(((1, 0), true)));
+		assert_eq!(loader.finish(2), None);
+	}
+
+	#[test]
+	fn cancel_drops_the_job() {
+		let mut loader = Loader::default();
+		open(&mut loader, (0, 0), "a");
+
+		assert_eq!(loader.cancel(), Some("a".to_string()));
+		assert_eq!(loader.cancel(), None);
+		assert!(loader.loading().is_none());
+		assert!(!loader.is_decoding((0, 0)));
+		assert_eq!(loader.finish(1), None);
+	}
+
+	#[test]
+	fn forget_drops_every_job() {
+		let mut loader = Loader::default();
+		open(&mut loader, (0, 0), "a");
+		prefetch(&mut loader, (1, 0), "b");
+
+		loader.forget();
+		assert!(loader.loading().is_none());
+		assert_eq!(loader.finish(1), None);
+		assert_eq!(loader.finish(2), None);
+
+		// New jobs don't reuse old generations
+		open(&mut loader, (0, 0), "a");
+		assert!(loader.is_waiting_for(3));
+	}
+
+	#[test]
+	fn prefetch_never_replaces_current() {
+		let mut loader = Loader::default();
+		open(&mut loader, (0, 0), "a");
+		prefetch(&mut loader, (1, 0), "b");
+		assert!(loader.is_waiting_for(1));
+		assert_eq!(loader.loading().map(|(name, _)| name), Some("a"));
+
+		assert_eq!(loader.finish(2), Some(((1, 0), false)));
+		assert!(loader.is_waiting_for(1));
+		assert_eq!(loader.finish(1), Some(((0, 0), true)));
+	}
+
+	#[test]
+	fn open_reuses_running_prefetch() {
+		let mut loader = Loader::default();
+		prefetch(&mut loader, (1, 0), "b");
+		assert!(loader.loading().is_none());
+
+		open(&mut loader, (1, 0), "b");
+		assert!(loader.is_waiting_for(1));
+		assert_eq!(loader.finish(1), Some(((1, 0), true)));
+	}
+
+	#[test]
+	fn stop_waiting_keeps_the_job() {
+		let mut loader = Loader::default();
+		open(&mut loader, (0, 0), "a");
+
+		loader.stop_waiting();
+		assert!(loader.loading().is_none());
+		assert!(loader.is_decoding((0, 0)));
+		assert_eq!(loader.finish(1), Some(((0, 0), false)));
+	}
+
+	#[test]
+	fn preview_then_full_image() {
+		let mut loader = Loader::default();
+		let (sender, receiver) = std::sync::mpsc::channel();
+		let preview_sender = sender.clone();
+		let source = ImageSource::Memory(
+			"big.jpg".to_string(),
+			std::sync::Arc::new(crate::utils::tests::big_jpeg()),
+		);
+		let width = |decoded: &Decoded| decoded.frames[0].0.width();
+		loader.open(
+			(0, 0),
+			&source,
+			&options(),
+			move |generation, result: Result<Decoded, LoadError>| {
+				let decoded = result.unwrap();
+				sender
+					.send((generation, width(&decoded), decoded.preview_of))
+					.unwrap();
+			},
+			Some(move |generation, decoded: Decoded| {
+				preview_sender
+					.send((generation, width(&decoded), decoded.preview_of))
+					.unwrap();
+			}),
+		);
+
+		// Either can be first, the preview is only shown while the full image is still wanted
+		let mut results = [receiver.recv().unwrap(), receiver.recv().unwrap()];
+		results.sort_by_key(|(_, width, _)| *width);
+		assert_eq!(results[0], (1, 640, Some((2560, 1600))));
+		assert_eq!(results[1], (1, 2560, None));
+
+		assert!(loader.is_waiting_for(1));
+		assert_eq!(loader.finish(1), Some(((0, 0), true)));
+		// A preview that comes after the full image is thrown away
+		assert!(!loader.is_waiting_for(1));
+	}
+}
@@ -1,27 +1,198 @@
+mod actions;
+mod animation;
+mod cache;
+mod cli;
+mod color;
+mod convert;
+mod error;
+mod headless;
+mod info;
+mod loader;
+mod pages;
+mod playlist;
+mod raw;
 mod settings;
 mod shaders;
+mod state;
+mod svg;
+mod texture;
+mod theme;
 mod ui;
 mod utils;
+mod view;
+
+use clap::{CommandFactory, FromArgMatches};
+
+// The display profile is a file of its own, a broken one is a config error
+fn decode_options(settings: &settings::Settings) -> utils::DecodeOptions {
+	utils::DecodeOptions::new(&settings.image).unwrap_or_else(|e| {
+		eprintln!("{}", e);
+		std::process::exit(cli::ExitCodes::CONFIG)
+	})
+}
 
 fn main() {
+	// Argument parsing
+	// Exits with ExitCodes::USAGE on bad arguments
+	// Matches are kept to tell typed flags from defaults
+	let matches = cli::Args::command().get_matches();
+	let args = cli::Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+	// Config
+	// `--config` has to exist, the default file is optional
+	if let Some(config) = &args.config {
+		if !config.exists() {
+			eprintln!("Config file `{}` doesn't exist!", config.display());
+			std::process::exit(cli::ExitCodes::NO_INPUT)
+		}
+	}
+	let (settings, config_path) = match &args.command {
+		// Works even when the config file is broken
+		Some(cli::Command::Config { default: true }) => (settings::Settings::default(), None),
+		_ => match settings::Settings::load(args.config.as_deref()) {
+			Ok(loaded) => loaded,
+			Err(e) => {
+				eprintln!("{}", e);
+				std::process::exit(cli::ExitCodes::CONFIG)
+			}
+		},
+	};
+
 	// Debug
-	if settings::ProgramSettings::PRINT_DEBUG_INFO {
+	if settings.program.print_debug_info {
 		println!("--- R-liv v{} ---", std::env!("CARGO_PKG_VERSION"));
 		println!("ImGui v{}", imgui::dear_imgui_version());
 	}
 
-	// Argument parsing
+	// Headless subcommands
+	if let Some(command) = &args.command {
+		// Only `--config` is shared, clap can't tell the rest apart
+		if !args.images.is_empty() {
+			cli::Args::command()
+				.error(
+					clap::ErrorKind::ArgumentConflict,
+					"Images go after the subcommand",
+				)
+				.exit()
+		}
+
+		// Viewer flags would be silently ignored
+		for flag in ["zoom", "fit", "fullscreen"] {
+			if matches.value_source(flag) == Some(clap::ValueSource::CommandLine) {
+				cli::Args::command()
+					.error(
+						clap::ErrorKind::ArgumentConflict,
+						format!("`--{}` only applies to the viewer, not subcommands", flag),
+					)
+					.exit()
+			}
+		}
 
-	let args: Vec<String> = std::env::args().collect();
-	if args.len() == 1 {
-		eprintln!("No images selected!");
-		std::process::exit(1)
+		let code = match command {
+			cli::Command::Formats => cli::print_formats(),
+			cli::Command::Config { .. } => {
+				settings::print_config(&settings, config_path.as_deref())
+			}
+			cli::Command::Info { images, json } => {
+				info::print_info(images, *json, settings.image.auto_orient)
+			}
+			cli::Command::Convert {
+				images,
+				to,
+				out_dir,
+				quality,
+				resize,
+				rotate,
+				flip,
+				overwrite,
+			} => convert::convert(
+				images,
+				&convert::ConvertOptions {
+					format: *to,
+					out_dir: out_dir.clone(),
+					quality: *quality,
+					resize: *resize,
+					rotate: *rotate,
+					flip: *flip,
+					overwrite: *overwrite,
+					// Files are written without a profile, so they have to be sRGB
+					decode: utils::DecodeOptions {
+						auto_orient: settings.image.auto_orient,
+						color: settings.image.color_management.then(color::Target::srgb),
+					},
+				},
+			),
+			cli::Command::Render {
+				image,
+				size,
+				zoom,
+				offset,
+				fit,
+				rotate,
+				output,
+			} => headless::render(
+				image,
+				&headless::RenderOptions {
+					size: *size,
+					view: view::View {
+						rotation: *rotate,
+						..view::View::new(*fit, *zoom, *offset)
+					},
+					output: output.clone(),
+					background: settings.window.background_color,
+					decode: decode_options(&settings),
+					hdr: settings.hdr.clone(),
+				},
+			),
+		};
+		std::process::exit(code)
 	}
 
+	// Session
+	let state = if settings.session.remember {
+		state::State::load()
+	} else {
+		state::State::default()
+	};
+
+	// Images
+	// Nothing given but something is piped in, so read stdin
+	// Nothing at all, or an empty stdin, opens what was open last time
+	let images = if !args.images.is_empty() {
+		args.images.clone()
+	} else if !std::io::IsTerminal::is_terminal(&std::io::stdin())
+		&& !utils::ImageSource::stdin_is_empty()
+	{
+		vec!["-".to_string()]
+	} else if let Some(last) = state.last_opened() {
+		vec![last.to_string_lossy().to_string()]
+	} else {
+		cli::Args::command()
+			.error(
+				clap::ErrorKind::MissingRequiredArgument,
+				"No images selected!",
+			)
+			.exit()
+	};
+
+	let playlist = match playlist::Playlist::new(&images) {
+		Ok(playlist) => playlist,
+		Err(e) => {
+			eprintln!("{}", e);
+			std::process::exit(cli::ExitCodes::NO_INPUT)
+		}
+	};
+
 	// Create window and main loop
-	//TODO Better argument parsing
-	ui::window(&args[args.len() - 1]);
+	// Errors from here on are shown in the window
+	// The config is watched, so the default one can be created while running
+	let config_path = args
+		.config
+		.clone()
+		.or_else(settings::Settings::default_path);
+	let decode = decode_options(&settings);
+	ui::window(playlist, &args, settings, decode, config_path, state);
 
 	// Exit
-	std::process::exit(0)
+	std::process::exit(cli::ExitCodes::SUCCESS)
 }
@@ -0,0 +1,282 @@
+// pages.rs
+// Files that hold more than one image, e.g. scanned multi-page TIFF or ICO with several sizes
+// The image crate only ever decodes one of them
+
+extern crate image;
+extern crate tiff;
+
+use image::error::{DecodingError, ImageError, UnsupportedError, UnsupportedErrorKind};
+use image::{DynamicImage, ImageBuffer, ImageFormat};
+
+use std::io::Cursor;
+
+// Number of pages, 1 for single image formats
+pub fn count(format: Option<ImageFormat>, bytes: &[u8]) -> usize {
+	match format {
+		Some(ImageFormat::Tiff) => tiff_pages(bytes),
+		Some(ImageFormat::Ico) => ico_entries(bytes).map_or(1, |entries| entries.len()),
+		_ => 1,
+	}
+	.max(1)
+}
+
+// Decode a page, starting at 0
+pub fn decode(
+	format: Option<ImageFormat>,
+	bytes: &[u8],
+	page: usize,
+) -> Result<DynamicImage, ImageError> {
+	match format {
+		Some(ImageFormat::Tiff) => decode_tiff(bytes, page),
+		Some(ImageFormat::Ico) => decode_ico(bytes, page),
+		_ => Err(unsupported(format, "Pages".to_string())),
+	}
+}
+
+fn unsupported(format: Option<ImageFormat>, feature: String) -> ImageError {
+	let hint = format.map_or(image::error::ImageFormatHint::Unknown, Into::into);
+	ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+		hint,
+		UnsupportedErrorKind::GenericFeature(feature),
+	))
+}
+
+fn tiff_error(error: tiff::TiffError) -> ImageError {
+	ImageError::Decoding(DecodingError::new(ImageFormat::Tiff.into(), error))
+}
+
+// Pages up to the first broken one
+fn tiff_pages(bytes: &[u8]) -> usize {
+	let mut decoder = match tiff::decoder::Decoder::new(Cursor::new(bytes)) {
+		Ok(decoder) => decoder,
+		Err(_) => return 1,
+	};
+
+	let mut pages = 1;
+	while decoder.more_images() && decoder.next_image().is_ok() {
+		pages += 1;
+	}
+
+	pages
+}
+
+fn decode_tiff(bytes: &[u8], page: usize) -> Result<DynamicImage, ImageError> {
+	use tiff::decoder::DecodingResult as D;
+	use tiff::ColorType as C;
+
+	let mut decoder = tiff::decoder::Decoder::new(Cursor::new(bytes))
+		.map_err(tiff_error)?
+		.with_limits(tiff::decoder::Limits::unlimited());
+	decoder.seek_to_image(page).map_err(tiff_error)?;
+
+	let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+	let color = decoder.colortype().map_err(tiff_error)?;
+	let data = decoder.read_image().map_err(tiff_error)?;
+
+	// Same color types the image crate supports, plus float ones it doesn't
+	let image = match (color, data) {
+		(C::Gray(8), D::U8(data)) => {
+			ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma8)
+		}
+		(C::GrayA(8), D::U8(data)) => {
+			ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLumaA8)
+		}
+		(C::RGB(8), D::U8(data)) => {
+			ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb8)
+		}
+		(C::RGBA(8), D::U8(data)) => {
+			ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba8)
+		}
+		(C::Gray(16), D::U16(data)) => {
+			ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma16)
+		}
+		(C::GrayA(16), D::U16(data)) => {
+			ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLumaA16)
+		}
+		(C::RGB(16), D::U16(data)) => {
+			ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb16)
+		}
+		(C::RGBA(16), D::U16(data)) => {
+			ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba16)
+		}
+		// There are no float gray images, e.g. depth maps become RGB
+		(C::Gray(32), D::F32(data)) => {
+			let data = data.iter().flat_map(|&v| [v, v, v]).collect();
+			ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb32F)
+		}
+		(C::RGB(32), D::F32(data)) => {
+			ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb32F)
+		}
+		(C::RGBA(32), D::F32(data)) => {
+			ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba32F)
+		}
+		// The image crate turns some, e.g. CMYK, into RGB, but only for the first page
+		_ if page == 0 => return image::load_from_memory_with_format(bytes, ImageFormat::Tiff),
+		(color, _) => {
+			return Err(unsupported(
+				Some(ImageFormat::Tiff),
+				format!("Pages in {:?}", color),
+			))
+		}
+	};
+
+	image.ok_or_else(|| {
+		tiff_error(tiff::TiffError::FormatError(
+			tiff::TiffFormatError::InconsistentSizesEncountered,
+		))
+	})
+}
+
+// Directory entries of every image in an ICO, biggest first
+// so page 0 is the one the image crate would pick
+fn ico_entries(bytes: &[u8]) -> Option<Vec<[u8; 16]>> {
+	let count = u16::from_le_bytes(bytes.get(4..6)?.try_into().ok()?) as usize;
+
+	let mut entries = (0..count)
+		.map(|i| bytes.get(6 + i * 16..6 + (i + 1) * 16)?.try_into().ok())
+		.collect::<Option<Vec<[u8; 16]>>>()?;
+
+	// (bits per pixel, area), 0 means 256 pixels
+	let size = |entry: &[u8; 16]| {
+		let side = |byte: u8| if byte == 0 { 256 } else { byte as u32 };
+		let bits = u16::from_le_bytes([entry[6], entry[7]]);
+		(bits, side(entry[0]) * side(entry[1]))
+	};
+	entries.sort_by_key(|entry| std::cmp::Reverse(size(entry)));
+
+	Some(entries)
+}
+
+// Copies the entry into an ICO of its own, then lets the image crate decode that
+fn decode_ico(bytes: &[u8], page: usize) -> Result<DynamicImage, ImageError> {
+	let broken = || {
+		ImageError::Decoding(DecodingError::new(
+			ImageFormat::Ico.into(),
+			format!("Entry {} is broken", page + 1),
+		))
+	};
+
+	let mut entry = ico_entries(bytes)
+		.and_then(|entries| entries.get(page).copied())
+		.ok_or_else(broken)?;
+
+	let length = u32::from_le_bytes(entry[8..12].try_into().unwrap()) as usize;
+	let offset = u32::from_le_bytes(entry[12..16].try_into().unwrap()) as usize;
+	let data = bytes
+		.get(offset..offset.checked_add(length).ok_or_else(broken)?)
+		.ok_or_else(broken)?;
+
+	// Same type (icon or cursor), one entry and its data right after the directory
+	let header = [0, 0, bytes[2], bytes[3], 1, 0];
+	entry[12..16].copy_from_slice(&22u32.to_le_bytes());
+	let ico = [&header[..], &entry[..], data].concat();
+
+	image::load_from_memory_with_format(&ico, ImageFormat::Ico)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tiff::encoder::{colortype, TiffEncoder};
+
+	// Pages of the given sizes, RGB8 and then 16 bit gray
+	fn tiff(sizes: &[(u32, u32)]) -> Vec<u8> {
+		let mut bytes = Vec::new();
+		let mut encoder = TiffEncoder::new(Cursor::new(&mut bytes)).unwrap();
+		for (i, &(width, height)) in sizes.iter().enumerate() {
+			let pixels = (width * height) as usize;
+			if i == 0 {
+				encoder
+					.write_image::<colortype::RGB8>(width, height, &vec![200; pixels * 3])
+					.unwrap();
+			} else {
+				encoder
+					.write_image::<colortype::Gray16>(width, height, &vec![1000; pixels])
+					.unwrap();
+			}
+		}
+		bytes
+	}
+
+	#[test]
+	fn tiff_pages() {
+		let bytes = tiff(&[(4, 3), (2, 5), (7, 1)]);
+		assert_eq!(count(Some(ImageFormat::Tiff), &bytes), 3);
+
+		let first = decode_tiff(&bytes, 0).unwrap();
+		assert_eq!((first.width(), first.height()), (4, 3));
+		assert!(matches!(first, DynamicImage::ImageRgb8(_)));
+
+		let second = decode_tiff(&bytes, 1).unwrap();
+		assert_eq!((second.width(), second.height()), (2, 5));
+		assert!(matches!(&second, DynamicImage::ImageLuma16(image) if image[(1, 4)].0 == [1000]));
+
+		let third = decode_tiff(&bytes, 2).unwrap();
+		assert_eq!((third.width(), third.height()), (7, 1));
+
+		assert!(decode_tiff(&bytes, 3).is_err());
+	}
+
+	#[test]
+	fn cmyk_tiff() {
+		let mut bytes = Vec::new();
+		let mut encoder = TiffEncoder::new(Cursor::new(&mut bytes)).unwrap();
+		let cyan = [255, 0, 0, 0].repeat(6);
+		encoder
+			.write_image::<colortype::CMYK8>(3, 2, &cyan)
+			.unwrap();
+		encoder
+			.write_image::<colortype::CMYK8>(3, 2, &cyan)
+			.unwrap();
+
+		let first = decode_tiff(&bytes, 0).unwrap();
+		assert_eq!((first.width(), first.height()), (3, 2));
+		assert_eq!(first.to_rgb8()[(0, 0)].0, [0, 255, 255]);
+
+		// Only the image crate knows how and it only decodes the first page
+		assert!(matches!(
+			decode_tiff(&bytes, 1),
+			Err(ImageError::Unsupported(_))
+		));
+	}
+
+	// ICO directory with (width, height, bits per pixel) entries, no image data
+	fn ico(entries: &[(u8, u8, u16)]) -> Vec<u8> {
+		let mut bytes = vec![0, 0, 1, 0];
+		bytes.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+		for &(width, height, bits) in entries {
+			bytes.extend_from_slice(&[width, height, 0, 0, 1, 0]);
+			bytes.extend_from_slice(&bits.to_le_bytes());
+			bytes.extend_from_slice(&[0; 8]);
+		}
+		bytes
+	}
+
+	#[test]
+	fn ico_order() {
+		let bytes = ico(&[(16, 16, 32), (0, 0, 32), (48, 48, 8), (32, 32, 32)]);
+		let sizes = ico_entries(&bytes)
+			.unwrap()
+			.iter()
+			.map(|entry| (entry[0], entry[1], entry[6]))
+			.collect::<Vec<_>>();
+		// 0 is 256 pixels, more colors win over size
+		assert_eq!(sizes, [(0, 0, 32), (32, 32, 32), (16, 16, 32), (48, 48, 8)]);
+		assert_eq!(count(Some(ImageFormat::Ico), &bytes), 4);
+	}
+
+	#[test]
+	fn broken_ico() {
+		let mut bytes = ico(&[(16, 16, 32), (32, 32, 32)]);
+		// The second entry is cut off
+		bytes.truncate(6 + 16 + 8);
+		assert!(ico_entries(&bytes).is_none());
+		assert!(ico_entries(&[0, 0, 1]).is_none());
+		assert_eq!(count(Some(ImageFormat::Ico), &bytes), 1);
+
+		// Data outside the file
+		let bytes = ico(&[(16, 16, 32)]);
+		assert!(decode_ico(&bytes, 0).is_err());
+		assert!(decode_ico(&bytes, 1).is_err());
+	}
+}
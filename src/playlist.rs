@@ -0,0 +1,291 @@
+// playlist.rs
+// Ordered list of images the user can flip through
+
+use crate::utils::ImageSource;
+
+use std::path::{Path, PathBuf};
+
+pub struct Playlist {
+	files: Vec<ImageSource>,
+	index: usize,
+}
+
+impl Playlist {
+	// Build a playlist from command line paths
+	// Directories are expanded to the images inside them (not recursive)
+	// A single file opens it's whole directory, starting at that file, like JPEGView
+	// `-` reads an image from stdin
+	pub fn new(paths: &[String]) -> Result<Playlist, String> {
+		if let [single] = paths {
+			let path = Path::new(single);
+			if single != "-" && path.is_file() {
+				return Ok(Playlist::from_sibling(path));
+			}
+		}
+
+		Playlist::from_paths(paths)
+	}
+
+	// Exactly the given files and directories, nothing next to them
+	pub fn from_paths(paths: &[String]) -> Result<Playlist, String> {
+		let mut files = Vec::new();
+		let mut read_stdin = false;
+		for path in paths {
+			if path == "-" {
+				if read_stdin {
+					return Err("Stdin can only be read once!".to_string());
+				}
+				read_stdin = true;
+
+				let source =
+					ImageSource::stdin().map_err(|e| format!("Can't read stdin: {}", e))?;
+				files.push(source);
+				continue;
+			}
+
+			let path = Path::new(path);
+
+			if path.is_dir() {
+				files.extend(Playlist::read_dir(path)?.into_iter().map(ImageSource::File));
+			} else if path.exists() {
+				// Explicitly given files are kept even with an unknown extension
+				files.push(ImageSource::File(path.to_path_buf()));
+			} else {
+				return Err(format!("File `{}` doesn't exist!", path.display()));
+			}
+		}
+
+		if files.is_empty() {
+			return Err("No images found!".to_string());
+		}
+
+		Ok(Playlist { files, index: 0 })
+	}
+
+	// Playlist of every image next to `file`
+	fn from_sibling(file: &Path) -> Playlist {
+		let parent = match file.parent() {
+			Some(parent) if !parent.as_os_str().is_empty() => parent,
+			_ => Path::new("."),
+		};
+
+		// If the directory can't be read just show the one file
+		let mut files = Playlist::read_dir(parent).unwrap_or_default();
+
+		let index = match files.iter().position(|f| f.file_name() == file.file_name()) {
+			Some(index) => index,
+			None => {
+				// Unsupported extension, still show it
+				files.insert(0, file.to_path_buf());
+				0
+			}
+		};
+		files[index] = file.to_path_buf();
+
+		Playlist {
+			files: files.into_iter().map(ImageSource::File).collect(),
+			index,
+		}
+	}
+
+	// Supported images in a directory, sorted by name
+	fn read_dir(dir: &Path) -> Result<Vec<PathBuf>, String> {
+		let entries = std::fs::read_dir(dir)
+			.map_err(|e| format!("Can't read directory `{}`: {}", dir.display(), e))?;
+
+		let mut files: Vec<PathBuf> = entries
+			.filter_map(|entry| entry.ok())
+			.map(|entry| entry.path())
+			.filter(|path| path.is_file() && Playlist::is_supported(path))
+			.collect();
+
+		files.sort_by_key(|path| path.file_name().unwrap_or_default().to_ascii_lowercase());
+
+		Ok(files)
+	}
+
+	// Judge by extension, opening every file would be too slow
+	// SVG and RAW aren't an image::ImageFormat, see svg.rs and raw.rs
+	pub fn is_supported(path: &Path) -> bool {
+		match image::ImageFormat::from_path(path) {
+			Ok(format) => format.can_read() && format.reading_enabled(),
+			Err(_) => path
+				.extension()
+				.map(|extension| extension.to_ascii_lowercase())
+				.is_some_and(|extension| {
+					["svg", "svgz", "cr2", "nef", "arw", "dng"]
+						.iter()
+						.any(|known| extension == *known)
+				}),
+		}
+	}
+
+	pub fn sources(&self) -> &[ImageSource] {
+		&self.files
+	}
+
+	pub fn current(&self) -> &ImageSource {
+		&self.files[self.index]
+	}
+
+	pub fn index(&self) -> usize {
+		self.index
+	}
+
+	pub fn len(&self) -> usize {
+		self.files.len()
+	}
+
+	// Wraps around at the end
+	pub fn next(&mut self) {
+		self.index = (self.index + 1) % self.files.len();
+	}
+
+	// Wraps around at the start
+	pub fn previous(&mut self) {
+		self.index = (self.index + self.files.len() - 1) % self.files.len();
+	}
+
+	// Indices up to distance away from the current one, closest first
+	// Wraps around like next and previous, the current one isn't included
+	pub fn neighbours(&self, distance: usize) -> Vec<usize> {
+		let len = self.files.len();
+		let mut indices = Vec::new();
+
+		for step in 1..=distance.min(len / 2) {
+			for index in [(self.index + step) % len, (self.index + len - step) % len] {
+				if index != self.index && !indices.contains(&index) {
+					indices.push(index);
+				}
+			}
+		}
+
+		indices
+	}
+
+	pub fn first(&mut self) {
+		self.index = 0;
+	}
+
+	pub fn last(&mut self) {
+		self.index = self.files.len() - 1;
+	}
+
+	// Out of range is ignored
+	pub fn select(&mut self, index: usize) {
+		if index < self.files.len() {
+			self.index = index;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Empty files are enough, only names and extensions are looked at
+	fn temp_dir(name: &str, files: &[&str]) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("r-liv-{}-{}", name, std::process::id()));
+		std::fs::remove_dir_all(&dir).ok();
+		std::fs::create_dir_all(dir.join("sub")).unwrap();
+		for file in files {
+			std::fs::write(dir.join(file), b"").unwrap();
+		}
+		dir
+	}
+
+	fn names(playlist: &Playlist) -> Vec<String> {
+		playlist
+			.sources()
+			.iter()
+			.map(|source| source.name())
+			.collect()
+	}
+
+	fn of_len(len: usize, index: usize) -> Playlist {
+		Playlist {
+			files: (0..len)
+				.map(|i| ImageSource::File(PathBuf::from(format!("{}.png", i))))
+				.collect(),
+			index,
+		}
+	}
+
+	#[test]
+	fn directories_expand_sorted() {
+		let dir = temp_dir(
+			"playlist-dir",
+			&["b.png", "A.jpg", "c.txt", "d.SVG", "e.nef", "sub/f.png"],
+		);
+
+		let playlist = Playlist::from_paths(&[dir.display().to_string()]).unwrap();
+		// Case insensitive, not recursive, unsupported extensions are left out
+		assert_eq!(names(&playlist), ["A.jpg", "b.png", "d.SVG", "e.nef"]);
+		assert_eq!(playlist.index(), 0);
+
+		// Given files are kept whatever their extension
+		let text = dir.join("c.txt").display().to_string();
+		let playlist = Playlist::from_paths(&[text, dir.display().to_string()]).unwrap();
+		assert_eq!(names(&playlist)[0], "c.txt");
+		assert_eq!(playlist.len(), 5);
+
+		std::fs::remove_dir_all(&dir).ok();
+	}
+
+	#[test]
+	fn missing_and_empty() {
+		let dir = temp_dir("playlist-empty", &["notes.txt"]);
+
+		assert!(Playlist::from_paths(&[dir.display().to_string()]).is_err());
+		let missing = dir.join("missing.png").display().to_string();
+		assert!(Playlist::from_paths(&[missing]).is_err());
+
+		std::fs::remove_dir_all(&dir).ok();
+	}
+
+	#[test]
+	fn single_file_opens_its_directory() {
+		let dir = temp_dir("playlist-sibling", &["a.png", "b.png", "c.png", "z.txt"]);
+
+		let playlist = Playlist::new(&[dir.join("b.png").display().to_string()]).unwrap();
+		assert_eq!(names(&playlist), ["a.png", "b.png", "c.png"]);
+		assert_eq!(playlist.current().name(), "b.png");
+
+		// Unsupported extension goes first
+		let playlist = Playlist::new(&[dir.join("z.txt").display().to_string()]).unwrap();
+		assert_eq!(names(&playlist), ["z.txt", "a.png", "b.png", "c.png"]);
+		assert_eq!(playlist.index(), 0);
+
+		std::fs::remove_dir_all(&dir).ok();
+	}
+
+	#[test]
+	fn wraps_around() {
+		let mut playlist = of_len(3, 0);
+		playlist.previous();
+		assert_eq!(playlist.index(), 2);
+		playlist.next();
+		assert_eq!(playlist.index(), 0);
+		playlist.last();
+		playlist.next();
+		assert_eq!(playlist.index(), 0);
+
+		playlist.select(7);
+		assert_eq!(playlist.index(), 0);
+		playlist.select(1);
+		assert_eq!(playlist.index(), 1);
+	}
+
+	#[test]
+	fn neighbours() {
+		assert_eq!(of_len(10, 5).neighbours(2), [6, 4, 7, 3]);
+		// Wraps around both ends
+		assert_eq!(of_len(10, 0).neighbours(1), [1, 9]);
+		assert_eq!(of_len(10, 9).neighbours(1), [0, 8]);
+		// Never more than the playlist has, no duplicates
+		assert_eq!(of_len(4, 0).neighbours(5), [1, 3, 2]);
+		assert_eq!(of_len(2, 0).neighbours(3), [1]);
+		assert!(of_len(1, 0).neighbours(3).is_empty());
+		assert!(of_len(10, 5).neighbours(0).is_empty());
+	}
+}
@@ -0,0 +1,313 @@
+// raw.rs
+// Camera RAW files, shown through the JPEG preview the camera embeds in them
+// Good enough to cull a shoot, r-liv doesn't develop the RAW data itself
+// CR2, NEF, ARW and DNG are all TIFF inside, EXIF is read from that like for any TIFF
+
+extern crate image;
+
+use crate::error::LoadError;
+use crate::utils::ImageSource;
+
+// Extensions and the name shown for them
+const KINDS: [(&str, &str); 4] = [
+	("cr2", "Cr2"),
+	("nef", "Nef"),
+	("arw", "Arw"),
+	("dng", "Dng"),
+];
+
+// SubIFDs point to more images, NEF and DNG keep their previews there
+const SUB_IFDS: u16 = 330;
+// Directories followed at most, broken files can point in circles
+const MAX_IFDS: usize = 32;
+
+// Which RAW format, None for everything else including plain TIFF
+// Canon and Adobe mark theirs, NEF and ARW are only told apart by extension
+pub fn kind(source: &ImageSource, bytes: &[u8]) -> Option<&'static str> {
+	let tiff = Tiff::new(bytes)?;
+
+	if bytes.get(8..10) == Some(&b"CR"[..]) {
+		return Some("Cr2");
+	}
+	// DNGVersion
+	if tiff.entry(tiff.first_ifd()?, 50706).is_some() {
+		return Some("Dng");
+	}
+
+	let extension = source.path()?.extension()?.to_ascii_lowercase();
+	KINDS
+		.iter()
+		.find(|(known, _)| extension == *known)
+		.map(|(_, name)| *name)
+}
+
+// Decode the biggest embedded preview, the RAW data around it is skipped
+pub fn decode(source: &ImageSource, bytes: &[u8]) -> Result<image::DynamicImage, LoadError> {
+	let jpeg = preview(bytes).ok_or_else(|| {
+		LoadError::UnsupportedFormat(
+			source.name(),
+			"There is no embedded preview and r-liv can't develop RAW data".to_string(),
+		)
+	})?;
+
+	image::load_from_memory_with_format(jpeg, image::ImageFormat::Jpeg)
+		.map_err(|e| LoadError::from_image(source, e))
+}
+
+// Biggest JPEG in any directory, by pixels
+fn preview(bytes: &[u8]) -> Option<&[u8]> {
+	let tiff = Tiff::new(bytes)?;
+
+	let mut ifds = vec![tiff.first_ifd()?];
+	let mut seen = Vec::new();
+	let mut jpegs = Vec::new();
+	while let Some(ifd) = ifds.pop() {
+		if seen.contains(&ifd) || seen.len() >= MAX_IFDS {
+			continue;
+		}
+		seen.push(ifd);
+
+		ifds.extend(tiff.next_ifd(ifd));
+		ifds.extend(tiff.values(ifd, SUB_IFDS));
+
+		// JPEGInterchangeFormat and its length, old style thumbnails and most previews
+		let interchange = (tiff.value(ifd, 0x201), tiff.value(ifd, 0x202));
+		// A single strip of JPEG, DNG previews
+		let strip = (tiff.value(ifd, 273), tiff.value(ifd, 279));
+		for (offset, length) in [interchange, strip] {
+			if let (Some(offset), Some(length)) = (offset, length) {
+				jpegs.extend(bytes.get(offset..offset.saturating_add(length)));
+			}
+		}
+	}
+
+	jpegs
+		.into_iter()
+		.filter_map(|jpeg| Some((jpeg, jpeg_size(jpeg)?)))
+		.max_by_key(|(_, (width, height))| *width as u64 * *height as u64)
+		.map(|(jpeg, _)| jpeg)
+}
+
+// Size of a JPEG the image crate can decode
+// The RAW data itself is often lossless JPEG, which it can't, so those are None too
+fn jpeg_size(jpeg: &[u8]) -> Option<(u16, u16)> {
+	if jpeg.get(0..2) != Some(&[0xFF, 0xD8][..]) {
+		return None;
+	}
+
+	let be_u16 = |offset: usize| {
+		Some(u16::from_be_bytes(
+			jpeg.get(offset..offset + 2)?.try_into().ok()?,
+		))
+	};
+
+	// Marker segments up to the frame header, each with its length after the marker
+	let mut offset = 2;
+	loop {
+		let marker = *jpeg.get(offset + 1)?;
+		if jpeg[offset] != 0xFF {
+			return None;
+		}
+		match marker {
+			// Fill bytes before a marker
+			0xFF => offset += 1,
+			// Baseline, extended and progressive
+			0xC0..=0xC2 => return Some((be_u16(offset + 7)?, be_u16(offset + 5)?)),
+			// Every other start of frame, e.g. lossless or arithmetic coding
+			0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => return None,
+			_ => offset += 2 + be_u16(offset + 2)? as usize,
+		}
+	}
+}
+
+// Just enough of TIFF to walk the directories
+struct Tiff<'a> {
+	bytes: &'a [u8],
+	little_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+	fn new(bytes: &'a [u8]) -> Option<Tiff<'a>> {
+		let little_endian = match bytes.get(0..4)? {
+			b"II*\0" => true,
+			b"MM\0*" => false,
+			_ => return None,
+		};
+
+		Some(Tiff {
+			bytes,
+			little_endian,
+		})
+	}
+
+	fn u16(&self, offset: usize) -> Option<u16> {
+		let bytes = self.bytes.get(offset..offset + 2)?.try_into().ok()?;
+		Some(if self.little_endian {
+			u16::from_le_bytes(bytes)
+		} else {
+			u16::from_be_bytes(bytes)
+		})
+	}
+
+	fn u32(&self, offset: usize) -> Option<u32> {
+		let bytes = self.bytes.get(offset..offset + 4)?.try_into().ok()?;
+		Some(if self.little_endian {
+			u32::from_le_bytes(bytes)
+		} else {
+			u32::from_be_bytes(bytes)
+		})
+	}
+
+	fn first_ifd(&self) -> Option<usize> {
+		Some(self.u32(4)? as usize)
+	}
+
+	// After the entry count and 12 bytes per entry, 0 is the end
+	fn next_ifd(&self, ifd: usize) -> Option<usize> {
+		let entries = self.u16(ifd)? as usize;
+		let next = self.u32(ifd + 2 + entries * 12)? as usize;
+		(next != 0).then_some(next)
+	}
+
+	// Offset of the entry with that tag
+	fn entry(&self, ifd: usize, tag: u16) -> Option<usize> {
+		let entries = self.u16(ifd)? as usize;
+		(0..entries)
+			.map(|i| ifd + 2 + i * 12)
+			.find(|&entry| self.u16(entry) == Some(tag))
+	}
+
+	// SHORT, LONG and IFD values, empty if the tag is missing or broken
+	fn values(&self, ifd: usize, tag: u16) -> Vec<usize> {
+		self.read_values(ifd, tag).unwrap_or_default()
+	}
+
+	// Tags with a single value, files with more are a different layout
+	fn value(&self, ifd: usize, tag: u16) -> Option<usize> {
+		match self.values(ifd, tag)[..] {
+			[value] => Some(value),
+			_ => None,
+		}
+	}
+
+	// Stored in the entry itself when they fit, elsewhere in the file otherwise
+	fn read_values(&self, ifd: usize, tag: u16) -> Option<Vec<usize>> {
+		let entry = self.entry(ifd, tag)?;
+		let count = self.u32(entry + 4)? as usize;
+		let size = match self.u16(entry + 2)? {
+			3 => 2,
+			4 | 13 => 4,
+			_ => return None,
+		};
+		let start = if count * size <= 4 {
+			entry + 8
+		} else {
+			self.u32(entry + 8)? as usize
+		};
+
+		(0..count.min(MAX_IFDS))
+			.map(|i| match size {
+				2 => self.u16(start + i * 2).map(|value| value as usize),
+				_ => self.u32(start + i * 4).map(|value| value as usize),
+			})
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::utils::tests::jpeg;
+
+	// Little endian IFD with (tag, type, value) entries, one value each
+	fn ifd(entries: &[(u16, u16, usize)], next: usize) -> Vec<u8> {
+		let mut ifd = (entries.len() as u16).to_le_bytes().to_vec();
+		for &(tag, kind, value) in entries {
+			ifd.extend_from_slice(&tag.to_le_bytes());
+			ifd.extend_from_slice(&kind.to_le_bytes());
+			ifd.extend_from_slice(&1u32.to_le_bytes());
+			ifd.extend_from_slice(&(value as u32).to_le_bytes());
+		}
+		ifd.extend_from_slice(&(next as u32).to_le_bytes());
+		ifd
+	}
+
+	// A thumbnail in IFD0 and a preview strip in its SubIFD, like NEF
+	fn raw(thumbnail: &[u8], preview: &[u8]) -> Vec<u8> {
+		let sub_ifd = 8 + 2 + 3 * 12 + 4;
+		let thumbnail_at = sub_ifd + 2 + 2 * 12 + 4;
+		let preview_at = thumbnail_at + thumbnail.len();
+
+		let mut bytes = b"II*\0\x08\0\0\0".to_vec();
+		bytes.extend(ifd(
+			&[
+				(0x201, 4, thumbnail_at),
+				(0x202, 4, thumbnail.len()),
+				(SUB_IFDS, 13, sub_ifd),
+			],
+			0,
+		));
+		bytes.extend(ifd(&[(273, 4, preview_at), (279, 4, preview.len())], 0));
+		bytes.extend_from_slice(thumbnail);
+		bytes.extend_from_slice(preview);
+		bytes
+	}
+
+	fn nef() -> ImageSource {
+		ImageSource::File(std::path::PathBuf::from("shot.nef"))
+	}
+
+	#[test]
+	fn biggest_preview() {
+		let (small, big) = (jpeg(8, 4), jpeg(32, 16));
+		for bytes in [raw(&small, &big), raw(&big, &small)] {
+			assert_eq!(kind(&nef(), &bytes), Some("Nef"));
+			assert_eq!(preview(&bytes), Some(&big[..]));
+			let image = decode(&nef(), &bytes).unwrap();
+			assert_eq!((image.width(), image.height()), (32, 16));
+		}
+	}
+
+	#[test]
+	fn lossless_jpeg_is_skipped() {
+		// Bigger, but a lossless start of frame the image crate can't decode
+		let lossless = b"\xFF\xD8\xFF\xC3\0\x0B\x08\x0B\xB8\x0F\xA0\x01\x01\x11\0";
+		assert_eq!(jpeg_size(lossless), None);
+
+		let small = jpeg(8, 4);
+		let bytes = raw(&small, lossless);
+		assert_eq!(preview(&bytes), Some(&small[..]));
+	}
+
+	#[test]
+	fn no_preview() {
+		let bytes = raw(b"thumbnail", b"raw data");
+		assert_eq!(preview(&bytes), None);
+		match decode(&nef(), &bytes) {
+			Err(LoadError::UnsupportedFormat(name, reason)) => {
+				assert_eq!(name, "shot.nef");
+				assert!(reason.contains("no embedded preview"));
+			}
+			other => panic!("{:?}", other),
+		}
+
+		// IFD0 pointing to itself
+		let mut bytes = b"II*\0\x08\0\0\0".to_vec();
+		bytes.extend(ifd(&[], 8));
+		assert_eq!(preview(&bytes), None);
+		assert_eq!(kind(&nef(), b"not a tiff"), None);
+	}
+
+	#[test]
+	fn jpeg_sizes() {
+		assert_eq!(jpeg_size(&jpeg(10, 5)), Some((10, 5)));
+
+		// Fill bytes before a marker
+		let mut filled = jpeg(10, 5);
+		filled.splice(2..2, [0xFF, 0xFF]);
+		assert_eq!(jpeg_size(&filled), Some((10, 5)));
+
+		assert_eq!(jpeg_size(&jpeg(10, 5)[..20]), None);
+		assert_eq!(jpeg_size(b"not a jpeg"), None);
+	}
+}
@@ -1,31 +1,609 @@
 // settings.rs
-// This file is for constants that a user might want to change
+// Everything a user might want to change, loaded from a TOML file
+// Missing keys fall back to the defaults below, unknown keys are errors
 
-pub struct ProgramSettings {}
-impl ProgramSettings {
+extern crate dirs;
+extern crate notify;
+extern crate serde;
+extern crate toml;
+
+use crate::actions;
+use crate::cli;
+use crate::theme;
+use crate::view;
+
+use serde::{Deserialize, Serialize};
+
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+	pub program: ProgramSettings,
+	pub window: WindowSettings,
+	pub image: ImageSettings,
+	pub theme: ThemeSettings,
+	pub session: SessionSettings,
+	pub cache: CacheSettings,
+	pub hdr: HdrSettings,
+	pub keybindings: actions::Keybindings,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProgramSettings {
 	// Print debug information on start
 	// Default: false
-	pub const PRINT_DEBUG_INFO: bool = false;
+	pub print_debug_info: bool,
 }
 
-pub struct WindowSettings {}
-impl WindowSettings {
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowSettings {
 	// Window title
 	// Default: R-Liv
-	pub const WINDOW_TITLE: &'static str = "R-Liv";
+	pub title: String,
+
+	// Full title, {title} is the setting above, {name} the image,
+	// {index} and {count} its place in the playlist,
+	// {position} is ` [index/count]` or nothing for a single image
+	// Default: {title} - {name}{position}
+	pub title_format: String,
 
 	// Debug menu open on start up
 	// Default: false
-	pub const DEBUG_MENU_OPEN: bool = false;
+	pub debug_menu_open: bool,
 
 	// Metadata menu open on start up
 	// Default: false
-	pub const METADATA_MENU_OPEN: bool = false;
+	pub metadata_menu_open: bool,
+
+	// Background behind the image, RGBA from 0.0 to 1.0
+	// Default: [0.05, 0.05, 0.05, 1.0]
+	pub background_color: [f32; 4],
+}
+
+impl Default for WindowSettings {
+	fn default() -> WindowSettings {
+		WindowSettings {
+			title: "R-Liv".to_string(),
+			title_format: "{title} - {name}{position}".to_string(),
+			debug_menu_open: false,
+			metadata_menu_open: false,
+			background_color: [0.05, 0.05, 0.05, 1.0],
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImageSettings {
+	// Zoom multiplier, percent per scroll wheel step
+	// Default: 10.0
+	pub zoom_multiplier: f32,
+
+	// Turn photos upright like the EXIF Orientation tag says
+	// Off shows the pixels exactly as they are stored, e.g. for forensics
+	// Default: true
+	pub auto_orient: bool,
+
+	// Convert images with an embedded ICC profile, e.g. Adobe RGB or Display P3
+	// Off shows the stored values as if they were sRGB
+	// Default: true
+	pub color_management: bool,
+
+	// ICC profile of the monitor to convert to, empty means sRGB
+	// `render` uses it too, `convert` always writes sRGB
+	// Default: empty
+	pub display_profile: String,
+
+	// Show a quarter size version of big JPEGs while the full one decodes
+	// Default: true
+	pub fast_preview: bool,
+}
+
+impl Default for ImageSettings {
+	fn default() -> ImageSettings {
+		ImageSettings {
+			zoom_multiplier: 10.0,
+			auto_orient: true,
+			color_management: true,
+			display_profile: String::new(),
+			fast_preview: true,
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeSettings {
+	// Colors of the overlay, dark, light or high_contrast
+	// Default: dark
+	pub preset: theme::ThemePreset,
+
+	// Scales the whole overlay, e.g. 2.0 on 4K monitors
+	// Default: 1.0
+	pub ui_scale: f32,
+
+	// Font size in pixels before ui_scale
+	// Default: 13.0
+	pub font_size: f32,
+
+	// Colors on top of the preset, RGBA from 0.0 to 1.0
+	// Names are ImGui's in snake_case, e.g. window_bg or button_hovered
+	// Default: none
+	pub colors: std::collections::BTreeMap<String, [f32; 4]>,
+}
+
+impl Default for ThemeSettings {
+	fn default() -> ThemeSettings {
+		ThemeSettings {
+			preset: theme::ThemePreset::Dark,
+			ui_scale: 1.0,
+			font_size: 13.0,
+			colors: Default::default(),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionSettings {
+	// Restore the window, menus and last image on the next start
+	// Menus then only come from the window settings on the very first start
+	// Default: true
+	pub remember: bool,
+
+	// Also restore zoom, pan and rotation of each image
+	// Default: false
+	pub remember_view: bool,
+}
+
+impl Default for SessionSettings {
+	fn default() -> SessionSettings {
+		SessionSettings {
+			remember: true,
+			remember_view: false,
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheSettings {
+	// Images decoded ahead in each direction of the playlist, 0 turns it off
+	// Default: 1
+	pub prefetch: usize,
+
+	// Video memory for images that aren't shown, in MB, 0 turns the cache off
+	// Default: 512
+	pub budget_mb: usize,
+
+	// The cache shrinks when the GPU has less than this left, in MB
+	// Only works on drivers that report free video memory
+	// Default: 256
+	pub keep_free_mb: usize,
+}
+
+impl Default for CacheSettings {
+	fn default() -> CacheSettings {
+		CacheSettings {
+			prefetch: 1,
+			budget_mb: 512,
+			keep_free_mb: 256,
+		}
+	}
+}
+
+// Float images, e.g. OpenEXR, Radiance HDR or 32 bit TIFF
+// These are the start values, they can be changed while viewing
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HdrSettings {
+	// Brightness in stops, +1.0 doubles it
+	// Default: 0.0
+	pub exposure: f32,
+
+	// Applied after tone mapping, 1.0 leaves the colors alone
+	// Default: 1.0
+	pub gamma: f32,
+
+	// clamp, reinhard or aces
+	// Default: clamp
+	pub tone_mapping: view::ToneMapping,
+}
+
+impl Default for HdrSettings {
+	fn default() -> HdrSettings {
+		HdrSettings {
+			exposure: 0.0,
+			gamma: 1.0,
+			tone_mapping: view::ToneMapping::Clamp,
+		}
+	}
+}
+
+impl HdrSettings {
+	pub const MAX_EXPOSURE: f32 = 20.0;
+	pub const MIN_GAMMA: f32 = 0.1;
+	pub const MAX_GAMMA: f32 = 10.0;
+}
+
+impl Settings {
+	// $XDG_CONFIG_HOME/r-liv/config.toml on Linux
+	pub fn default_path() -> Option<PathBuf> {
+		Some(dirs::config_dir()?.join("r-liv").join("config.toml"))
+	}
+
+	// `--config` has to exist, the default file doesn't
+	// Returns the settings and the file they came from
+	pub fn load(path: Option<&Path>) -> Result<(Settings, Option<PathBuf>), String> {
+		let path = match path {
+			Some(path) => path.to_path_buf(),
+			None => match Settings::default_path() {
+				Some(path) if path.exists() => path,
+				_ => return Ok((Settings::default(), None)),
+			},
+		};
+
+		let settings = Settings::read(&path)?;
+		Ok((settings, Some(path)))
+	}
+
+	pub fn read(path: &Path) -> Result<Settings, String> {
+		let text = std::fs::read_to_string(path)
+			.map_err(|e| format!("Can't read config `{}`: {}", path.display(), e))?;
+
+		Settings::parse(&text).map_err(|e| format!("Bad config `{}`: {}", path.display(), e))
+	}
+
+	pub fn parse(text: &str) -> Result<Settings, String> {
+		let settings: Settings = toml::from_str(text).map_err(|e| e.to_string())?;
+		settings.validate()?;
+
+		Ok(settings)
+	}
+
+	// Things serde can't check by itself
+	fn validate(&self) -> Result<(), String> {
+		if self.window.title.trim().is_empty() {
+			return Err("window.title must not be empty".to_string());
+		}
+
+		// Catch typos like {nmae}
+		let mut rest = self.window.title_format.as_str();
+		while let Some(start) = rest.find('{') {
+			let end = rest[start..].find('}').ok_or_else(|| {
+				format!(
+					"window.title_format has an unclosed `{{` in `{}`",
+					self.window.title_format
+				)
+			})? + start;
+			let key = &rest[start + 1..end];
+			if !["title", "name", "index", "count", "position"].contains(&key) {
+				return Err(format!(
+					"window.title_format has an unknown placeholder {{{}}}, \
+					 use {{title}}, {{name}}, {{index}}, {{count}} or {{position}}",
+					key
+				));
+			}
+			rest = &rest[end + 1..];
+		}
+
+		if self
+			.window
+			.background_color
+			.iter()
+			.any(|c| !(0.0..=1.0).contains(c))
+		{
+			return Err(format!(
+				"window.background_color values must be between 0.0 and 1.0, got {:?}",
+				self.window.background_color
+			));
+		}
+
+		// 100% or more would flip the image on a single scroll step
+		let zoom = self.image.zoom_multiplier;
+		if !(zoom > 0.0 && zoom < 100.0) {
+			return Err(format!(
+				"image.zoom_multiplier must be between 0 and 100 (exclusive), got {}",
+				zoom
+			));
+		}
+
+		if !(0.5..=4.0).contains(&self.theme.ui_scale) {
+			return Err(format!(
+				"theme.ui_scale must be between 0.5 and 4.0, got {}",
+				self.theme.ui_scale
+			));
+		}
+
+		if !(6.0..=72.0).contains(&self.theme.font_size) {
+			return Err(format!(
+				"theme.font_size must be between 6 and 72, got {}",
+				self.theme.font_size
+			));
+		}
+
+		for (name, color) in &self.theme.colors {
+			if theme::color_from_name(name).is_none() {
+				return Err(format!(
+					"theme.colors.{} is not an ImGui color, e.g. use window_bg, text or button_hovered",
+					name
+				));
+			}
+			if color.iter().any(|c| !(0.0..=1.0).contains(c)) {
+				return Err(format!(
+					"theme.colors.{} values must be between 0.0 and 1.0, got {:?}",
+					name, color
+				));
+			}
+		}
+
+		// Every prefetch is a thread and a full decoded image in memory
+		if self.cache.prefetch > 10 {
+			return Err(format!(
+				"cache.prefetch must be 10 or less, got {}",
+				self.cache.prefetch
+			));
+		}
+
+		let max = HdrSettings::MAX_EXPOSURE;
+		if !(-max..=max).contains(&self.hdr.exposure) {
+			return Err(format!(
+				"hdr.exposure must be between -{} and {}, got {}",
+				max, max, self.hdr.exposure
+			));
+		}
+
+		let (min, max) = (HdrSettings::MIN_GAMMA, HdrSettings::MAX_GAMMA);
+		if !(min..=max).contains(&self.hdr.gamma) {
+			return Err(format!(
+				"hdr.gamma must be between {} and {}, got {}",
+				min, max, self.hdr.gamma
+			));
+		}
+
+		actions::Bindings::new(&self.keybindings)?;
+
+		Ok(())
+	}
+
+	// Fill in window.title_format
+	// index is zero based
+	pub fn window_title(&self, name: &str, index: usize, count: usize) -> String {
+		let position = if count > 1 {
+			format!(" [{}/{}]", index + 1, count)
+		} else {
+			String::new()
+		};
+
+		self.window
+			.title_format
+			.replace("{title}", &self.window.title)
+			.replace("{name}", name)
+			.replace("{index}", &(index + 1).to_string())
+			.replace("{count}", &count.to_string())
+			.replace("{position}", &position)
+	}
+
+	// Effective settings as TOML, can be used as a config file
+	pub fn to_toml(&self) -> String {
+		toml::to_string_pretty(self).unwrap()
+	}
 }
 
-pub struct ImageSettings {}
-impl ImageSettings {
-	// Zoom multiplier
-	// Default: x
-	pub const ZOOM_MULTIPLIER: f32 = 10.0;
+// Directory the config file is in, `.` for a bare file name
+pub fn config_dir(path: &Path) -> PathBuf {
+	match path.parent() {
+		Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+		_ => PathBuf::from("."),
+	}
+}
+
+// Calls on_change whenever the file is written, created or removed
+// Watches the directory because editors often save by replacing the file
+// A directory that doesn't exist yet, e.g. on a fresh install, can't be watched,
+// so the closest one that does is watched instead and on_change is also called
+// when a directory on the way is made, watch again then to get closer
+// Returns the watcher and the directory it watches
+pub fn watch<F>(path: &Path, on_change: F) -> notify::Result<(notify::RecommendedWatcher, PathBuf)>
+where
+	F: Fn() + Send + 'static,
+{
+	use notify::Watcher;
+
+	let target = config_dir(path);
+	let dir = target
+		.ancestors()
+		.find(|dir| dir.is_dir())
+		.ok_or_else(|| notify::Error::path_not_found().add_path(target.clone()))?
+		.to_path_buf();
+	let file_name = path.file_name().map(|name| name.to_os_string());
+
+	let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+		let event = match event {
+			Ok(event) => event,
+			Err(_) => return,
+		};
+		if event.kind.is_access() {
+			return;
+		}
+		if event.paths.iter().any(|changed| {
+			changed.file_name() == file_name.as_deref() || target.starts_with(changed)
+		}) {
+			on_change();
+		}
+	})?;
+	watcher.watch(&dir, notify::RecursiveMode::NonRecursive)?;
+
+	Ok((watcher, dir))
+}
+
+// `r-liv config`
+pub fn print_config(settings: &Settings, path: Option<&Path>) -> i32 {
+	print!("{}", config_text(settings, path));
+
+	cli::ExitCodes::SUCCESS
+}
+
+// Where the settings came from as a comment, then the settings
+fn config_text(settings: &Settings, path: Option<&Path>) -> String {
+	let source = match path {
+		Some(path) => format!("# Loaded from {}", path.display()),
+		None => "# Defaults, no config file".to_string(),
+	};
+
+	format!("{}\n{}", source, settings.to_toml())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn error(text: &str) -> String {
+		Settings::parse(text).unwrap_err()
+	}
+
+	#[test]
+	fn empty_is_default() {
+		let settings = Settings::parse("").unwrap();
+		assert_eq!(settings.to_toml(), Settings::default().to_toml());
+	}
+
+	#[test]
+	fn out_of_range() {
+		let cases = [
+			("[window]\ntitle = \" \"", "window.title"),
+			(
+				"[window]\nbackground_color = [0.0, 0.0, 1.5, 1.0]",
+				"window.background_color",
+			),
+			("[image]\nzoom_multiplier = 0.0", "image.zoom_multiplier"),
+			("[image]\nzoom_multiplier = 100.0", "image.zoom_multiplier"),
+			("[theme]\nui_scale = 8.0", "theme.ui_scale"),
+			("[theme]\nfont_size = 2.0", "theme.font_size"),
+			(
+				"[theme.colors]\ntext = [2.0, 0.0, 0.0, 1.0]",
+				"theme.colors.text",
+			),
+			(
+				"[theme.colors]\ntxet = [1.0, 1.0, 1.0, 1.0]",
+				"theme.colors.txet",
+			),
+			("[cache]\nprefetch = 11", "cache.prefetch"),
+			("[hdr]\nexposure = -21.0", "hdr.exposure"),
+			("[hdr]\ngamma = 0.0", "hdr.gamma"),
+		];
+
+		for (text, key) in cases {
+			let e = error(text);
+			assert!(e.contains(key), "`{}` gave `{}`", text, e);
+		}
+
+		// Edges are fine
+		assert!(Settings::parse("[theme]\nui_scale = 4.0\nfont_size = 6.0").is_ok());
+		assert!(Settings::parse("[hdr]\nexposure = 20.0\ngamma = 10.0").is_ok());
+	}
+
+	#[test]
+	fn unknown_keys() {
+		assert!(error("[window]\ntitel = \"x\"").contains("unknown field `titel`"));
+		assert!(error("[windows]\ntitle = \"x\"").contains("unknown field `windows`"));
+		assert!(error("[image]\nzoom_multiplier = \"big\"").contains("invalid type"));
+	}
+
+	#[test]
+	fn title_placeholders() {
+		let e = error("[window]\ntitle_format = \"{title} {nmae}\"");
+		assert!(e.contains("unknown placeholder {nmae}"), "{}", e);
+		let e = error("[window]\ntitle_format = \"{title\"");
+		assert!(e.contains("unclosed"), "{}", e);
+
+		let settings = Settings::default();
+		assert_eq!(settings.window_title("a.png", 0, 1), "R-Liv - a.png");
+		assert_eq!(settings.window_title("b.png", 1, 3), "R-Liv - b.png [2/3]");
+
+		let settings = Settings::parse(
+			"[window]\ntitle = \"T\"\ntitle_format = \"{index}/{count} {name} {title}\"",
+		)
+		.unwrap();
+		assert_eq!(settings.window_title("c.png", 4, 9), "5/9 c.png T");
+	}
+
+	#[test]
+	fn config_output_round_trips() {
+		let text = "
+			[window]
+			title = \"Viewer\"
+			background_color = [0.25, 0.5, 0.75, 1.0]
+			[theme]
+			preset = \"light\"
+			ui_scale = 1.5
+			[theme.colors]
+			window_bg = [0.0, 0.0, 0.0, 0.5]
+			[hdr]
+			tone_mapping = \"aces\"
+			[keybindings]
+			next_image = [\"N\"]
+		";
+
+		for settings in [Settings::default(), Settings::parse(text).unwrap()] {
+			let printed = config_text(&settings, Some(Path::new("/tmp/config.toml")));
+			let parsed = Settings::parse(&printed).unwrap();
+			assert_eq!(parsed.to_toml(), settings.to_toml());
+		}
+		assert!(Settings::parse(&config_text(&Settings::default(), None)).is_ok());
+	}
+
+	#[test]
+	fn load() {
+		let path = std::env::temp_dir().join(format!("r-liv-config-{}.toml", std::process::id()));
+
+		assert!(Settings::load(Some(&path)).is_err());
+
+		std::fs::write(&path, "[cache]\nprefetch = 3\n").unwrap();
+		let (settings, loaded_from) = Settings::load(Some(&path)).unwrap();
+		assert_eq!(settings.cache.prefetch, 3);
+		assert_eq!(loaded_from.as_deref(), Some(path.as_path()));
+
+		// The file name is part of the error
+		std::fs::write(&path, "[cache]\nprefetch = 30\n").unwrap();
+		let e = Settings::read(&path).unwrap_err();
+		assert!(e.contains(&path.display().to_string()) && e.contains("cache.prefetch"));
+
+		std::fs::remove_file(&path).ok();
+	}
+
+	#[test]
+	fn watch_before_the_directory_exists() {
+		let base = std::env::temp_dir().join(format!("r-liv-watch-{}", std::process::id()));
+		std::fs::remove_dir_all(&base).ok();
+		std::fs::create_dir_all(&base).unwrap();
+		let path = base.join("r-liv").join("config.toml");
+
+		let (sender, receiver) = std::sync::mpsc::channel();
+		let changed = move || {
+			let sender = sender.clone();
+			move || {
+				sender.send(()).ok();
+			}
+		};
+		let wait = || receiver.recv_timeout(std::time::Duration::from_secs(5));
+
+		// Watches the closest directory until the real one is made
+		let (watcher, dir) = watch(&path, changed()).unwrap();
+		assert_eq!(dir, base);
+		std::fs::create_dir(base.join("r-liv")).unwrap();
+		assert!(wait().is_ok());
+		drop(watcher);
+
+		let (_watcher, dir) = watch(&path, changed()).unwrap();
+		assert_eq!(dir, config_dir(&path));
+		while receiver.try_recv().is_ok() {}
+		std::fs::write(&path, "").unwrap();
+		assert!(wait().is_ok());
+
+		std::fs::remove_dir_all(&base).ok();
+	}
 }
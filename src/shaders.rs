@@ -45,9 +45,48 @@ pub fn get_shader(version: &glium::Version) -> (String, String) {
 		out vec4 color;
 
 		uniform sampler2D tex;
-		
+		uniform int channels;
+		uniform bool decode_srgb;
+
+		// Float images, see settings.hdr
+		uniform bool hdr;
+		uniform float exposure;
+		uniform float gamma;
+		uniform int tone_mapping;
+
+		vec3 srgb_to_linear(vec3 c) {
+			return mix(c / 12.92, pow((c + 0.055) / 1.055, vec3(2.4)), step(0.04045, c));
+		}
+
+		// Exposure, then 0 clamp, 1 Reinhard or 2 ACES, then gamma
+		vec3 tone_map(vec3 c) {
+			c = max(c * exp2(exposure), 0.0);
+			if (tone_mapping == 1) {
+				c = c / (1.0 + c);
+			} else if (tone_mapping == 2) {
+				c = (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14);
+			}
+			return pow(clamp(c, 0.0, 1.0), vec3(1.0 / gamma));
+		}
+
 		void main() {
-			color = texture(tex, v_tex_coords);
+			vec4 texel = texture(tex, v_tex_coords);
+
+			// Gray textures only have red (and green for alpha)
+			if (channels == 1) {
+				texel = vec4(texel.rrr, 1.0);
+			} else if (channels == 2) {
+				texel = vec4(texel.rrr, texel.g);
+			}
+
+			if (decode_srgb) {
+				texel.rgb = srgb_to_linear(texel.rgb);
+			}
+			if (hdr) {
+				texel.rgb = tone_map(texel.rgb);
+			}
+
+			color = texel;
 		}
 		"#
 		.to_string();
@@ -73,8 +112,38 @@ pub fn get_shader(version: &glium::Version) -> (String, String) {
 		in vec2 v_tex_coords;
 		out vec4 color;
 		uniform sampler2D tex;
+		uniform int channels;
+		uniform bool decode_srgb;
+		uniform bool hdr;
+		uniform float exposure;
+		uniform float gamma;
+		uniform int tone_mapping;
+		vec3 srgb_to_linear(vec3 c) {
+			return mix(c / 12.92, pow((c + 0.055) / 1.055, vec3(2.4)), step(0.04045, c));
+		}
+		vec3 tone_map(vec3 c) {
+			c = max(c * exp2(exposure), 0.0);
+			if (tone_mapping == 1) {
+				c = c / (1.0 + c);
+			} else if (tone_mapping == 2) {
+				c = (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14);
+			}
+			return pow(clamp(c, 0.0, 1.0), vec3(1.0 / gamma));
+		}
 		void main() {
-			color = texture(tex, v_tex_coords);
+			vec4 texel = texture(tex, v_tex_coords);
+			if (channels == 1) {
+				texel = vec4(texel.rrr, 1.0);
+			} else if (channels == 2) {
+				texel = vec4(texel.rrr, texel.g);
+			}
+			if (decode_srgb) {
+				texel.rgb = srgb_to_linear(texel.rgb);
+			}
+			if (hdr) {
+				texel.rgb = tone_map(texel.rgb);
+			}
+			color = texel;
 		}
 		"#
 		.to_string();
@@ -97,8 +166,38 @@ pub fn get_shader(version: &glium::Version) -> (String, String) {
 		#version 100
 		varying lowp vec2 v_tex_coords;
 		uniform lowp sampler2D tex;
+		uniform int channels;
+		uniform bool decode_srgb;
+		uniform bool hdr;
+		uniform mediump float exposure;
+		uniform mediump float gamma;
+		uniform int tone_mapping;
+		mediump vec3 srgb_to_linear(mediump vec3 c) {
+			return mix(c / 12.92, pow((c + 0.055) / 1.055, vec3(2.4)), step(0.04045, c));
+		}
+		mediump vec3 tone_map(mediump vec3 c) {
+			c = max(c * exp2(exposure), 0.0);
+			if (tone_mapping == 1) {
+				c = c / (1.0 + c);
+			} else if (tone_mapping == 2) {
+				c = (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14);
+			}
+			return pow(clamp(c, 0.0, 1.0), vec3(1.0 / gamma));
+		}
 		void main() {
-			gl_FragColor = texture2D(tex, v_tex_coords);
+			mediump vec4 texel = texture2D(tex, v_tex_coords);
+			if (channels == 1) {
+				texel = vec4(texel.rrr, 1.0);
+			} else if (channels == 2) {
+				texel = vec4(texel.rrr, texel.g);
+			}
+			if (decode_srgb) {
+				texel.rgb = srgb_to_linear(texel.rgb);
+			}
+			if (hdr) {
+				texel.rgb = tone_map(texel.rgb);
+			}
+			gl_FragColor = texel;
 		}
 		"#
 		.to_string();
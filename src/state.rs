@@ -0,0 +1,175 @@
+// state.rs
+// What r-liv remembers between launches, see settings.session
+// Written by the viewer on exit, unlike the config which only the user writes
+
+extern crate dirs;
+extern crate serde;
+extern crate toml;
+
+use serde::{Deserialize, Serialize};
+
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct State {
+	// Last image and the directory it was in
+	pub last_file: Option<PathBuf>,
+	pub last_directory: Option<PathBuf>,
+
+	// Not saved while fullscreen
+	pub window: Option<WindowState>,
+
+	// None until the first exit, the window settings are used before that
+	pub menus: Option<MenuState>,
+
+	// Zoom, pan and rotation per image, newest last
+	pub views: Vec<ViewState>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct WindowState {
+	// Inner size in physical pixels
+	pub width: u32,
+	pub height: u32,
+	// Outer position, not every platform has one
+	pub x: Option<i32>,
+	pub y: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct MenuState {
+	pub debug: bool,
+	pub metadata: bool,
+	pub buttons: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ViewState {
+	pub path: PathBuf,
+	// Multi-page TIFF and ICO, missing in files from before pages had their own
+	#[serde(default)]
+	pub page: usize,
+	pub zoom: f32,
+	pub offset: (f32, f32),
+	pub rotation: u16,
+}
+
+impl State {
+	// Oldest views are forgotten after this many
+	const MAX_VIEWS: usize = 500;
+
+	// $XDG_STATE_HOME/r-liv/state.toml on Linux
+	pub fn path() -> Option<PathBuf> {
+		let dir = dirs::state_dir().or_else(dirs::data_local_dir)?;
+		Some(dir.join("r-liv").join("state.toml"))
+	}
+
+	// A missing or broken state file just means starting fresh
+	pub fn load() -> State {
+		let path = match State::path() {
+			Some(path) if path.exists() => path,
+			_ => return State::default(),
+		};
+
+		match std::fs::read_to_string(&path)
+			.map_err(|e| e.to_string())
+			.and_then(|text| toml::from_str(&text).map_err(|e| e.to_string()))
+		{
+			Ok(state) => state,
+			Err(e) => {
+				eprintln!("Ignoring session state `{}`: {}", path.display(), e);
+				State::default()
+			}
+		}
+	}
+
+	pub fn save(&self) -> Result<(), String> {
+		let path = State::path().ok_or_else(|| "No directory for the session state".to_string())?;
+		let error = |e: String| format!("Can't save session state `{}`: {}", path.display(), e);
+
+		if let Some(dir) = path.parent() {
+			std::fs::create_dir_all(dir).map_err(|e| error(e.to_string()))?;
+		}
+		let text = toml::to_string(self).map_err(|e| error(e.to_string()))?;
+
+		// Write next to it first so a crash can't leave half a file
+		let temp = path.with_extension("toml.tmp");
+		std::fs::write(&temp, text).map_err(|e| error(e.to_string()))?;
+		std::fs::rename(&temp, &path).map_err(|e| error(e.to_string()))
+	}
+
+	// Last image if it's still there, otherwise its directory
+	pub fn last_opened(&self) -> Option<PathBuf> {
+		self.last_file
+			.iter()
+			.chain(self.last_directory.iter())
+			.find(|path| path.exists())
+			.cloned()
+	}
+
+	pub fn view(&self, path: &Path, page: usize) -> Option<&ViewState> {
+		self.views
+			.iter()
+			.find(|view| view.path == path && view.page == page)
+	}
+
+	// None forgets the view, e.g. when it's back to the default
+	pub fn set_view(&mut self, path: &Path, page: usize, view: Option<ViewState>) {
+		self.views
+			.retain(|other| other.path != path || other.page != page);
+
+		if let Some(view) = view {
+			self.views.push(view);
+			if self.views.len() > State::MAX_VIEWS {
+				self.views.remove(0);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn view(path: &str, page: usize, zoom: f32) -> ViewState {
+		ViewState {
+			path: PathBuf::from(path),
+			page,
+			zoom,
+			offset: (0.0, 0.0),
+			rotation: 0,
+		}
+	}
+
+	#[test]
+	fn views_per_page() {
+		let mut state = State::default();
+		let path = Path::new("/pics/scan.tiff");
+		state.set_view(path, 0, Some(view("/pics/scan.tiff", 0, 2.0)));
+		state.set_view(path, 1, Some(view("/pics/scan.tiff", 1, 3.0)));
+
+		assert_eq!(state.view(path, 0).map(|view| view.zoom), Some(2.0));
+		assert_eq!(state.view(path, 1).map(|view| view.zoom), Some(3.0));
+		assert!(state.view(path, 2).is_none());
+
+		// Forgetting one page keeps the others
+		state.set_view(path, 0, None);
+		assert!(state.view(path, 0).is_none());
+		assert!(state.view(path, 1).is_some());
+	}
+
+	#[test]
+	fn views_without_page() {
+		let state: State = toml::from_str(
+			"[[views]]\npath = \"/pics/a.png\"\nzoom = 2.0\noffset = [1.0, 2.0]\nrotation = 90\n",
+		)
+		.unwrap();
+		assert_eq!(
+			state
+				.view(Path::new("/pics/a.png"), 0)
+				.map(|view| view.rotation),
+			Some(90)
+		);
+	}
+}
@@ -0,0 +1,229 @@
+// svg.rs
+// SVG and SVGZ through resvg, the image crate only knows pixels
+// The window rasterizes again when zoomed in, so vector art stays sharp
+
+extern crate resvg;
+
+use crate::error::LoadError;
+use crate::utils::ImageSource;
+
+use resvg::{tiny_skia, usvg};
+
+use std::sync::{Arc, OnceLock};
+
+// Longest side of a raster, bigger ones would need gigabytes of video memory
+const MAX_SIDE: f32 = 8192.0;
+
+// A parsed SVG, cheap to clone for rasterizer threads
+#[derive(Clone, Debug)]
+pub struct Vector {
+	tree: Arc<usvg::Tree>,
+}
+
+// Sniffed like image::guess_format, only files fall back to the extension
+pub fn is_svg(source: &ImageSource, bytes: &[u8]) -> bool {
+	if image::guess_format(bytes).is_ok() {
+		return false;
+	}
+
+	let extension = source
+		.path()
+		.and_then(|path| path.extension())
+		.map(|extension| extension.to_ascii_lowercase());
+	if extension.is_some_and(|extension| extension == "svg" || extension == "svgz") {
+		return true;
+	}
+
+	// XML declarations and comments can come before the root element
+	let start = String::from_utf8_lossy(&bytes[..bytes.len().min(1024)]);
+	let start = start.trim_start_matches('\u{feff}').trim_start();
+	start.starts_with('<') && start.contains("<svg")
+}
+
+// System fonts for <text>, scanning them takes a while so it's done once
+fn fonts() -> Arc<usvg::fontdb::Database> {
+	static FONTS: OnceLock<Arc<usvg::fontdb::Database>> = OnceLock::new();
+
+	FONTS
+		.get_or_init(|| {
+			let mut fonts = usvg::fontdb::Database::new();
+			fonts.load_system_fonts();
+
+			// fontdb picks Windows fonts for the generic families, Linux has others
+			let serif = [
+				"Times New Roman",
+				"DejaVu Serif",
+				"Liberation Serif",
+				"Noto Serif",
+			];
+			if let Some(family) = first_installed(&fonts, &serif) {
+				fonts.set_serif_family(family);
+			}
+			let sans_serif = ["Arial", "DejaVu Sans", "Liberation Sans", "Noto Sans"];
+			if let Some(family) = first_installed(&fonts, &sans_serif) {
+				fonts.set_sans_serif_family(family);
+			}
+			let monospace = [
+				"Courier New",
+				"DejaVu Sans Mono",
+				"Liberation Mono",
+				"Noto Sans Mono",
+			];
+			if let Some(family) = first_installed(&fonts, &monospace) {
+				fonts.set_monospace_family(family);
+			}
+
+			Arc::new(fonts)
+		})
+		.clone()
+}
+
+fn first_installed<'a>(fonts: &usvg::fontdb::Database, families: &[&'a str]) -> Option<&'a str> {
+	families.iter().copied().find(|family| {
+		fonts
+			.faces()
+			.any(|face| face.families.iter().any(|(name, _)| name == family))
+	})
+}
+
+impl Vector {
+	pub fn parse(source: &ImageSource, bytes: &[u8]) -> Result<Vector, LoadError> {
+		let options = usvg::Options {
+			// Linked images are relative to the file
+			resources_dir: source
+				.path()
+				.and_then(|path| path.parent())
+				.map(|dir| dir.to_path_buf()),
+			fontdb: fonts(),
+			..Default::default()
+		};
+
+		let tree = usvg::Tree::from_data(bytes, &options)
+			.map_err(|e| LoadError::Decode(source.name(), e.to_string()))?;
+
+		Ok(Vector {
+			tree: Arc::new(tree),
+		})
+	}
+
+	// Size the SVG asks for, what the viewer treats as the image size
+	pub fn size(&self) -> (u32, u32) {
+		let size = self.tree.size();
+		(
+			(size.width().ceil() as u32).max(1),
+			(size.height().ceil() as u32).max(1),
+		)
+	}
+
+	// Scale of the first raster, the size the SVG asks for unless that's too big
+	pub fn first_scale(&self) -> f32 {
+		self.max_scale().min(1.0)
+	}
+
+	// Scale to rasterize at so image pixels are at least texel_size screen pixels big
+	// Powers of two, so zooming in doesn't rasterize on every step
+	pub fn scale_for(&self, texel_size: f32) -> f32 {
+		texel_size
+			.max(1.0)
+			.log2()
+			.ceil()
+			.exp2()
+			.min(self.max_scale())
+	}
+
+	// Longest side at MAX_SIDE
+	fn max_scale(&self) -> f32 {
+		let (width, height) = self.size();
+		MAX_SIDE / width.max(height) as f32
+	}
+
+	// Pixels at scale times the size, with straight alpha like the decoders give
+	// Scales past MAX_SIDE are capped, None if tiny-skia still refuses the size
+	pub fn rasterize(&self, scale: f32) -> Option<image::RgbaImage> {
+		let scale = scale.min(self.max_scale());
+		let (width, height) = self.size();
+		let (width, height) = (
+			((width as f32 * scale).round() as u32).max(1),
+			((height as f32 * scale).round() as u32).max(1),
+		);
+
+		let mut pixmap = tiny_skia::Pixmap::new(width, height)?;
+		let size = self.tree.size();
+		let transform = tiny_skia::Transform::from_scale(
+			width as f32 / size.width(),
+			height as f32 / size.height(),
+		);
+		resvg::render(&self.tree, transform, &mut pixmap.as_mut());
+
+		let data = pixmap
+			.pixels()
+			.iter()
+			.flat_map(|pixel| {
+				let pixel = pixel.demultiply();
+				[pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()]
+			})
+			.collect();
+
+		image::RgbaImage::from_raw(width, height, data)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn memory(bytes: &[u8]) -> ImageSource {
+		ImageSource::Memory("-".to_string(), Arc::new(bytes.to_vec()))
+	}
+
+	fn vector(width: u32, height: u32) -> Vector {
+		let svg = format!(
+			"<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\"/>",
+			width, height
+		);
+		Vector::parse(&memory(svg.as_bytes()), svg.as_bytes()).unwrap()
+	}
+
+	#[test]
+	fn sniffing() {
+		let svg = b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>";
+		assert!(is_svg(&memory(svg), svg));
+		let prolog = b"\xEF\xBB\xBF<?xml version=\"1.0\"?>\n<!-- drawn by hand -->\n<svg/>";
+		assert!(is_svg(&memory(prolog), prolog));
+
+		assert!(!is_svg(
+			&memory(b"<html><body/></html>"),
+			b"<html><body/></html>"
+		));
+		assert!(!is_svg(&memory(b"svg"), b"svg"));
+
+		// Files go by their extension too, e.g. compressed SVGZ
+		let file = |name: &str| ImageSource::File(std::path::PathBuf::from(name));
+		assert!(is_svg(&file("drawing.SVGZ"), b"\x1F\x8B\x08"));
+		assert!(!is_svg(&file("drawing.txt"), b"\x1F\x8B\x08"));
+
+		// Other formats win over a wrong extension
+		let icon = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/icon.png")).unwrap();
+		assert!(!is_svg(&file("icon.svg"), &icon));
+	}
+
+	#[test]
+	fn scales() {
+		let svg = vector(100, 50);
+		assert_eq!(svg.size(), (100, 50));
+		assert_eq!(svg.first_scale(), 1.0);
+
+		// Never below the size it asks for, powers of two above
+		assert_eq!(svg.scale_for(0.25), 1.0);
+		assert_eq!(svg.scale_for(1.0), 1.0);
+		assert_eq!(svg.scale_for(1.5), 2.0);
+		assert_eq!(svg.scale_for(4.0), 4.0);
+		assert_eq!(svg.scale_for(5.0), 8.0);
+		// Up to 8192 pixels on the longest side
+		assert_eq!(svg.scale_for(1000.0), 81.92);
+
+		let wide = vector(16384, 10);
+		assert_eq!(wide.first_scale(), 0.5);
+		assert_eq!(wide.rasterize(1.0).unwrap().dimensions(), (8192, 5));
+	}
+}
@@ -0,0 +1,260 @@
+// texture.rs
+// Images on the GPU, in the smallest format that keeps all of the data
+// 8 bit color uses sRGB textures so the GPU decodes it,
+// everything else is decoded in the fragment shader
+// Images bigger than GL_MAX_TEXTURE_SIZE are split into a grid of tiles
+
+extern crate glium;
+extern crate image;
+
+use glium::texture::{ClientFormat, MipmapsOption, RawImage2d, UncompressedFloatFormat};
+
+use std::borrow::Cow;
+
+pub enum TextureKind {
+	// Sampling returns linear color
+	Srgb(glium::texture::SrgbTexture2d),
+	// Sampling returns the stored values as they are
+	Linear(glium::texture::Texture2d),
+}
+
+// Part of an image, the whole image if it fits into one texture
+pub struct Tile {
+	pub texture: TextureKind,
+	// Top left corner in image pixels
+	pub x: u32,
+	pub y: u32,
+	// In image pixels, the texture only differs for previews
+	width: u32,
+	height: u32,
+}
+
+impl Tile {
+	fn new(texture: TextureKind, x: u32, y: u32) -> Tile {
+		let (width, height) = match &texture {
+			TextureKind::Srgb(texture) => texture.dimensions(),
+			TextureKind::Linear(texture) => texture.dimensions(),
+		};
+
+		Tile {
+			texture,
+			x,
+			y,
+			width,
+			height,
+		}
+	}
+
+	pub fn dimensions(&self) -> (u32, u32) {
+		(self.width, self.height)
+	}
+}
+
+pub struct ImageTexture {
+	// Row by row from the top left
+	pub tiles: Vec<Tile>,
+	// Of the whole image
+	width: u32,
+	height: u32,
+	// 1 gray, 2 gray and alpha, 3 RGB, 4 RGBA
+	pub channels: u8,
+	// Values are sRGB encoded but the GPU doesn't know, see shaders.rs
+	pub decode_srgb: bool,
+	// Float data from HDR formats, can go above 1.0, see settings.hdr
+	pub hdr: bool,
+	// For the debug menu, e.g. `RGB8 sRGB`
+	pub format_name: &'static str,
+	// Bytes of video memory, see needed_memory
+	pub memory: usize,
+}
+
+// How a color type is uploaded
+struct Layout {
+	client: ClientFormat,
+	gpu: UncompressedFloatFormat,
+	channels: u8,
+	bytes_per_pixel: usize,
+	decode_srgb: bool,
+	name: &'static str,
+}
+
+impl Layout {
+	fn new(
+		client: ClientFormat,
+		gpu: UncompressedFloatFormat,
+		channels: u8,
+		bytes_per_pixel: usize,
+		decode_srgb: bool,
+		name: &'static str,
+	) -> Layout {
+		Layout {
+			client,
+			gpu,
+			channels,
+			bytes_per_pixel,
+			decode_srgb,
+			name,
+		}
+	}
+
+	// Mipmaps add a third
+	fn memory(&self, width: u32, height: u32) -> usize {
+		width as usize * height as usize * self.bytes_per_pixel * 4 / 3
+	}
+}
+
+impl ImageTexture {
+	pub fn dimensions(&self) -> (u32, u32) {
+		(self.width, self.height)
+	}
+
+	// Show it as if it had another size, e.g. a preview in place of the full image
+	pub fn stretch(&mut self, width: u32, height: u32) {
+		let scale =
+			|value: u32, from: u32, to: u32| (value as u64 * to as u64 / from as u64) as u32;
+
+		for tile in &mut self.tiles {
+			tile.x = scale(tile.x, self.width, width);
+			tile.y = scale(tile.y, self.height, height);
+			tile.width = scale(tile.width, self.width, width);
+			tile.height = scale(tile.height, self.height, height);
+		}
+		self.width = width;
+		self.height = height;
+	}
+
+	// Bytes of video memory for an image, with mipmaps
+	pub fn needed_memory(image: &image::DynamicImage) -> usize {
+		ImageTexture::layout(image).memory(image.width(), image.height())
+	}
+
+	fn layout(image: &image::DynamicImage) -> Layout {
+		use image::DynamicImage::*;
+		use ClientFormat as C;
+		use UncompressedFloatFormat as G;
+
+		match image {
+			// Single channel sRGB textures are an extension, so decode in the shader
+			ImageLuma8(_) => Layout::new(C::U8, G::U8, 1, 1, true, "R8"),
+			ImageLumaA8(_) => Layout::new(C::U8U8, G::U8U8, 2, 2, true, "RG8"),
+			ImageRgb8(_) => Layout::new(C::U8U8U8, G::U8U8U8, 3, 3, false, "RGB8 sRGB"),
+			// There are no 16 bit sRGB textures
+			ImageLuma16(_) => Layout::new(C::U16, G::U16, 1, 2, true, "R16"),
+			ImageLumaA16(_) => Layout::new(C::U16U16, G::U16U16, 2, 4, true, "RG16"),
+			ImageRgb16(_) => Layout::new(C::U16U16U16, G::U16U16U16, 3, 6, true, "RGB16"),
+			ImageRgba16(_) => Layout::new(C::U16U16U16U16, G::U16U16U16U16, 4, 8, true, "RGBA16"),
+			// Float images come from HDR formats, they are linear already
+			ImageRgb32F(_) => Layout::new(C::F32F32F32, G::F32F32F32, 3, 12, false, "RGB32F"),
+			ImageRgba32F(_) => {
+				Layout::new(C::F32F32F32F32, G::F32F32F32F32, 4, 16, false, "RGBA32F")
+			}
+			// RGBA8 and anything new in the image crate
+			_ => Layout::new(C::U8U8U8U8, G::U8U8U8U8, 4, 4, false, "RGBA8 sRGB"),
+		}
+	}
+
+	// Tiles are at most max_size pixels on each side
+	pub fn upload<F: glium::backend::Facade>(
+		facade: &F,
+		image: image::DynamicImage,
+		max_size: u32,
+	) -> Result<ImageTexture, glium::texture::TextureCreationError> {
+		let layout = ImageTexture::layout(&image);
+		let (width, height) = (image.width(), image.height());
+		let hdr = matches!(
+			image,
+			image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_)
+		);
+
+		let tiles = if width <= max_size && height <= max_size {
+			vec![Tile::new(
+				ImageTexture::upload_tile(facade, image, &layout)?,
+				0,
+				0,
+			)]
+		} else {
+			let mut tiles = Vec::new();
+			for y in (0..height).step_by(max_size as usize) {
+				for x in (0..width).step_by(max_size as usize) {
+					// Copies the pixels, only huge images end up here
+					let tile =
+						image.crop_imm(x, y, max_size.min(width - x), max_size.min(height - y));
+					tiles.push(Tile::new(
+						ImageTexture::upload_tile(facade, tile, &layout)?,
+						x,
+						y,
+					));
+				}
+			}
+			tiles
+		};
+
+		Ok(ImageTexture {
+			tiles,
+			width,
+			height,
+			channels: layout.channels,
+			decode_srgb: layout.decode_srgb,
+			hdr,
+			format_name: layout.name,
+			memory: layout.memory(width, height),
+		})
+	}
+
+	// Rows stay top to bottom, the quad flips them instead of the CPU
+	fn upload_tile<F: glium::backend::Facade>(
+		facade: &F,
+		image: image::DynamicImage,
+		layout: &Layout,
+	) -> Result<TextureKind, glium::texture::TextureCreationError> {
+		use image::DynamicImage::*;
+
+		let (width, height) = (image.width(), image.height());
+		let mipmaps = MipmapsOption::AutoGeneratedMipmaps;
+
+		// Pixels as they are, in the format from the layout
+		macro_rules! raw {
+			($data:expr) => {
+				RawImage2d {
+					data: Cow::Owned($data),
+					width,
+					height,
+					format: layout.client,
+				}
+			};
+		}
+		macro_rules! linear {
+			($buffer:expr) => {
+				TextureKind::Linear(glium::texture::Texture2d::with_format(
+					facade,
+					raw!($buffer.into_raw()),
+					layout.gpu,
+					mipmaps,
+				)?)
+			};
+		}
+
+		Ok(match image {
+			ImageRgb8(buffer) => TextureKind::Srgb(glium::texture::SrgbTexture2d::with_format(
+				facade,
+				raw!(buffer.into_raw()),
+				glium::texture::SrgbFormat::U8U8U8,
+				mipmaps,
+			)?),
+			ImageLuma8(buffer) => linear!(buffer),
+			ImageLumaA8(buffer) => linear!(buffer),
+			ImageLuma16(buffer) => linear!(buffer),
+			ImageLumaA16(buffer) => linear!(buffer),
+			ImageRgb16(buffer) => linear!(buffer),
+			ImageRgba16(buffer) => linear!(buffer),
+			ImageRgb32F(buffer) => linear!(buffer),
+			ImageRgba32F(buffer) => linear!(buffer),
+			image => TextureKind::Srgb(glium::texture::SrgbTexture2d::with_format(
+				facade,
+				raw!(image.into_rgba8().into_raw()),
+				glium::texture::SrgbFormat::U8U8U8U8,
+				mipmaps,
+			)?),
+		})
+	}
+}
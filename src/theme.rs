@@ -0,0 +1,134 @@
+// theme.rs
+// Colors, sizes and the font of the ImGui overlay, see settings.theme
+// Always starts from ImGui's default style so it can be reapplied on reload
+
+extern crate imgui;
+
+use crate::settings;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThemePreset {
+	Dark,
+	Light,
+	HighContrast,
+}
+
+// Config name of a color, e.g. FrameBgHovered is frame_bg_hovered
+pub fn color_name(color: imgui::StyleColor) -> String {
+	let mut name = String::new();
+
+	for c in format!("{:?}", color).chars() {
+		if c.is_uppercase() && !name.is_empty() {
+			name.push('_');
+		}
+		name.push(c.to_ascii_lowercase());
+	}
+
+	name
+}
+
+pub fn color_from_name(name: &str) -> Option<imgui::StyleColor> {
+	imgui::StyleColor::VARIANTS
+		.into_iter()
+		.find(|color| color_name(*color) == name)
+}
+
+// base is the style ImGui started with
+pub fn apply_style(style: &mut imgui::Style, base: &imgui::Style, theme: &settings::ThemeSettings) {
+	*style = *base;
+
+	match theme.preset {
+		ThemePreset::Dark => {
+			style.use_dark_colors();
+		}
+		ThemePreset::Light => {
+			style.use_light_colors();
+		}
+		ThemePreset::HighContrast => high_contrast(style),
+	}
+
+	style.window_rounding = 0.0;
+	style.window_border_size = 1.0;
+	style.alpha = 0.9;
+	style.window_padding = [2.0, 2.0];
+	style.window_title_align = [1.0, 0.5];
+
+	// Nothing see-through and thick borders
+	if theme.preset == ThemePreset::HighContrast {
+		style.alpha = 1.0;
+		style.window_border_size = 2.0;
+		style.frame_border_size = 1.0;
+	}
+
+	for (name, color) in &theme.colors {
+		if let Some(index) = color_from_name(name) {
+			style[index] = *color;
+		}
+	}
+
+	style.scale_all_sizes(theme.ui_scale);
+}
+
+// Pure black and white, dark blue behind white text for anything interactive
+// and yellow for small accents
+fn high_contrast(style: &mut imgui::Style) {
+	use imgui::StyleColor::*;
+
+	style.use_dark_colors();
+
+	let black = [0.0, 0.0, 0.0, 1.0];
+	let white = [1.0, 1.0, 1.0, 1.0];
+	let yellow = [1.0, 1.0, 0.0, 1.0];
+	let blue = [0.0, 0.0, 0.55, 1.0];
+	let light_blue = [0.0, 0.25, 0.8, 1.0];
+
+	for color in [
+		WindowBg,
+		ChildBg,
+		PopupBg,
+		FrameBg,
+		TitleBg,
+		MenuBarBg,
+		ScrollbarBg,
+		Button,
+	] {
+		style[color] = black;
+	}
+	for color in [Text, Border, Separator, ScrollbarGrab] {
+		style[color] = white;
+	}
+	for color in [
+		ButtonHovered,
+		FrameBgHovered,
+		HeaderHovered,
+		Header,
+		TitleBgActive,
+	] {
+		style[color] = blue;
+	}
+	for color in [ButtonActive, FrameBgActive, HeaderActive, TextSelectedBg] {
+		style[color] = light_blue;
+	}
+	for color in [
+		CheckMark,
+		ScrollbarGrabHovered,
+		ScrollbarGrabActive,
+		NavHighlight,
+	] {
+		style[color] = yellow;
+	}
+	style[TextDisabled] = [0.75, 0.75, 0.75, 1.0];
+}
+
+// The font is rendered at its final size so it stays sharp when scaled
+pub fn font(theme: &settings::ThemeSettings) -> imgui::FontSource<'static> {
+	imgui::FontSource::DefaultFontData {
+		config: Some(imgui::FontConfig {
+			size_pixels: theme.font_size * theme.ui_scale,
+			..imgui::FontConfig::default()
+		}),
+	}
+}
@@ -1,4 +1,4 @@
-// shaders.rs
+// ui.rs
 // Responsible for all GUI related stuff (so most of the code)
 // Beware of spaghetti
 
@@ -6,30 +6,55 @@ extern crate glium;
 extern crate image;
 extern crate imgui;
 extern crate imgui_glium_renderer;
+extern crate notify;
 
+use crate::actions;
+use crate::animation;
+use crate::cache;
+use crate::cli;
+use crate::error;
+use crate::loader;
+use crate::playlist;
 use crate::settings;
-use crate::shaders;
+use crate::state;
+use crate::texture;
+use crate::theme;
 use crate::utils;
-
-use cgmath::Matrix4;
+use crate::view;
 
 struct WindowData {
 	// OpenGl
 	//gl_event_loop: glutin::event_loop::EventLoop<()>,
 	gl_display: glium::Display,
-	uniform: [[f32; 4]; 4],
 
 	// ImGui
 	im_builder: imgui::Context,
 	im_renderer: imgui_glium_renderer::Renderer,
+	base_style: imgui::Style, // Before the theme
+
+	// Config file
+	settings: settings::Settings,
+	decode: utils::DecodeOptions, // From settings.image, holds the display profile
+	config_path: Option<std::path::PathBuf>, // Reloaded when it changes
+	// Stops watching when dropped, with the directory it watches
+	config_watcher: Option<(notify::RecommendedWatcher, std::path::PathBuf)>,
+
+	// Images
+	playlist: playlist::Playlist,
 
 	// Texture
-	image_texture: Option<glium::texture::SrgbTexture2d>,
-	zoom_level: f32, // Zoom
-	// Multiplier for the uniform
-	offset: (f32, f32), // Pan
-	// Both are in relative pixels from the center of the image
-	// Also .1 needs to be reversed
+	loader: loader::Loader, // Decodes on other threads
+	proxy: glium::glutin::event_loop::EventLoopProxy<UserEvent>, // Where decoded images are sent
+	resize_to_image: bool,  // Fit the window to the first image
+	last_draw: std::time::Instant, // Animates the loading indicator
+	image: Option<animation::Animation>, // Textures of every frame
+	image_key: Option<loader::ImageKey>, // Playlist image and page on screen
+	page: usize,            // Of the current playlist image
+	rasterizing: Option<(loader::ImageKey, f32)>, // SVG raster being made, see sharpen
+	cache: cache::TextureCache, // Textures of other images, see settings.cache
+	animation_speed: f32,   // For all animations, 1.0 is normal
+	hdr: settings::HdrSettings, // Starts as settings.hdr, changed by the HDR actions
+	view: view::View,       // Zoom and pan
 	last_offset: (f32, f32), // Last Pan
 
 	// UI
@@ -38,241 +63,835 @@ struct WindowData {
 	example_menu: bool,
 	metadata_menu: bool,
 	action_menu: bool,
+
+	// Saved on exit, see settings.session
+	state: state::State,
+	shown: Option<(std::path::PathBuf, usize)>, // Canonical path and page the view belongs to
+
+	// Shown in a popup until the user picks what to do
+	error: Option<PopupError>,
+	gl_error: Option<String>, // Last draw error, they repeat every frame
+
+	// Keyboard
+	bindings: actions::Bindings, // From settings.keybindings
+	modifiers: glium::glutin::event::ModifiersState,
+	quit: bool, // Set by Action::Quit, the event loop exits
+}
+
+// Sent to the event loop from other threads
+#[derive(Debug)]
+enum UserEvent {
+	ConfigChanged,
+	// (generation, result) from the loader
+	Decoded(u64, Result<utils::Decoded, error::LoadError>),
+	// (generation, quick low resolution version) while the full image decodes
+	Preview(u64, utils::Decoded),
+	// (image, scale, pixels) of an SVG rasterized again
+	Rasterized(loader::ImageKey, f32, image::DynamicImage),
+}
+
+// Only errors about the current image can be retried or skipped
+enum PopupError {
+	Image(String),
+	Other(String), // e.g. a broken config or OpenGL
+}
+
+// Buttons in the error popup
+enum ErrorAction {
+	Retry,
+	Skip,
+	Dismiss,
+	Open(usize), // Playlist index
 }
 
 impl WindowData {
-	fn texel_size(&self) -> f32 {
-		let window_size = self.gl_display.gl_window().window().inner_size();
+	// Set title from settings.window.title_format
+	fn title(settings: &settings::Settings, playlist: &playlist::Playlist) -> String {
+		settings.window_title(&playlist.current().name(), playlist.index(), playlist.len())
+	}
 
-		(window_size.width.min(window_size.height) as f32
-			/ self
-				.image_texture
-				.as_ref()
-				.unwrap()
-				.width()
-				.max(self.image_texture.as_ref().unwrap().height()) as f32)
-			* self.zoom_level
+	// Swap the texture for the current playlist image, reusing the window
+	// On error there is no image and the error popup shows up
+	fn open_current(&mut self) {
+		self.gl_display
+			.gl_window()
+			.window()
+			.set_title(&WindowData::title(&self.settings, &self.playlist));
+
+		let key = (self.playlist.index(), self.page);
+		let preview = self.image.as_ref().is_some_and(|image| image.preview);
+		if self.image_key == Some(key) && !preview {
+			// Already on screen, e.g. flipped back before the next one was decoded
+			self.loader.stop_waiting();
+		} else if let Some(image) = self.cache.take(key) {
+			self.loader.stop_waiting();
+			self.set_image(Some((key, image)));
+			self.prefetch();
+		} else {
+			// The old image stays until the new one is decoded
+			let done = self.decode_callback();
+			let preview = self
+				.settings
+				.image
+				.fast_preview
+				.then(|| self.preview_callback());
+			self.loader
+				.open(key, self.playlist.current(), &self.decode, done, preview);
+		}
+		self.error = None;
+
+		// Every image starts fitted, unless its view is remembered
+		self.remember_view();
+		self.view.offset = (0.0, 0.0);
+		self.view.zoom_level = 1.0;
+		self.view.rotation = 0;
+		// Canonical, so `./a.png` and `/home/u/a.png` share one view
+		// Pages of a TIFF or ICO each have their own
+		let page = self.page;
+		self.shown = self.playlist.current().path().map(|path| {
+			let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+			(path, page)
+		});
+		if let Some(view) = self
+			.shown
+			.as_ref()
+			.and_then(|(path, page)| self.state.view(path, *page))
+		{
+			if self.settings.session.remember_view {
+				self.view.offset = view.offset;
+				self.view.zoom_level = view.zoom;
+				self.view.rotation = view.rotation;
+			}
+		}
+		self.gl_display.gl_window().window().request_redraw();
 	}
 
-	fn calculate_uniform(&self, window_width: f32, window_height: f32) -> [[f32; 4]; 4] {
-		let image_width = (self.image_texture.as_ref().unwrap().get_width()) as f32;
-		//? Why does height need unwrap() but width doesn't?
-		let image_height = (self.image_texture.as_ref().unwrap().get_height().unwrap()) as f32;
+	// Where the loader sends decoded images
+	fn decode_callback(
+		&self,
+	) -> impl FnOnce(u64, Result<utils::Decoded, error::LoadError>) + Send + 'static {
+		let proxy = self.proxy.clone();
+		move |generation, result| {
+			proxy
+				.send_event(UserEvent::Decoded(generation, result))
+				.ok();
+		}
+	}
 
-		let image_ratio = (image_width / image_height) as f32;
-		let window_ratio = window_width / window_height as f32;
+	// Where the loader sends previews of big images
+	fn preview_callback(&self) -> impl FnOnce(u64, utils::Decoded) + Send + 'static {
+		let proxy = self.proxy.clone();
+		move |generation, decoded| {
+			proxy
+				.send_event(UserEvent::Preview(generation, decoded))
+				.ok();
+		}
+	}
 
-		// From ArturKovacs/emulsion
-		let mut scale_x = 1f32;
-		let mut scale_y = 1f32;
+	// Pages of the current playlist image, 1 until it's decoded
+	fn pages(&self) -> usize {
+		match (&self.image, self.image_key) {
+			(Some(image), Some((index, _))) if index == self.playlist.index() => image.pages,
+			_ => 1,
+		}
+	}
 
-		if image_ratio < window_ratio {
-			scale_x = ((image_ratio / window_ratio) * window_width).floor() / window_width
-		} else {
-			scale_y =
-				((window_ratio / image_ratio) * window_height as f32).floor() / window_height as f32
-		}
-
-		// Make just the scales transform
-		let transform = Matrix4::from_nonuniform_scale(scale_x, scale_y, 1.0);
-
-		// Pan
-		// Holy shit this is *the* worst thing I've ever written.
-		// It's goal is to offset the matrix.
-		// *Oh and x needs to be reversed
-		// The result we are looking for is f(image width in px, 0) -> (1,0)
-		// and (0, image height in px) -> (1,0).
-		// The basic is this:
-			// (window_width / 2.0 + self.offset.0) / (window_width / 2.0)
-		// BUT the center is 0.0 so
-			// (image_size_px.0 + self.offset.0) / (image_size_px.0) - 1.0
-		// BUT panning doesn't work correctly when zoomed out
-			// (image_size_px.0 * self.zoom_level + self.offset.0) / (image_size_px.0 * self.zoom_level) - 1.0
-		// BUT when zooming in again it zooms into the center but I don't care enough
-		// TODO
-
-		let image_size_px = (
-			window_width / 2.0,
-			window_height / 2.0, //? For some reason removing / scale_y is better?
+	// Images that are likely opened next, the pages next to the current one
+	// and the first pages of the images around it
+	fn wanted_soon(&self) -> Vec<loader::ImageKey> {
+		let index = self.playlist.index();
+		let pages = self.pages();
+
+		let mut keys = Vec::new();
+		if self.page + 1 < pages {
+			keys.push((index, self.page + 1));
+		}
+		if self.page > 0 {
+			keys.push((index, self.page - 1));
+		}
+		keys.extend(
+			self.playlist
+				.neighbours(self.settings.cache.prefetch)
+				.into_iter()
+				.map(|index| (index, 0)),
+		);
+
+		keys
+	}
+
+	// Decode the images around the current one in the background
+	fn prefetch(&mut self) {
+		if self.settings.cache.budget_mb == 0 {
+			return;
+		}
+
+		for key in self.wanted_soon() {
+			if self.image_key == Some(key)
+				|| self.cache.contains(key)
+				|| self.loader.is_decoding(key)
+			{
+				continue;
+			}
+			let done = self.decode_callback();
+			self.loader
+				.prefetch(key, &self.playlist.sources()[key.0], &self.decode, done);
+		}
+	}
+
+	// Bytes the cache may use right now
+	fn cache_budget(&self) -> usize {
+		let free = self.gl_display.get_free_video_memory();
+		self.cache.budget(&self.settings.cache, free)
+	}
+
+	// Show an image, the one that was on screen goes to the cache
+	fn set_image(&mut self, image: Option<(loader::ImageKey, animation::Animation)>) {
+		let old = self.image_key.zip(self.image.take());
+
+		self.image_key = image.as_ref().map(|(key, _)| *key);
+		self.image = image.map(|(_, image)| image);
+		// Another texture might draw fine
+		self.gl_error = None;
+
+		// Previews are thrown away, the full image is decoding anyway
+		if let Some((key, image)) = old.filter(|(_, image)| !image.preview) {
+			let budget = self.cache_budget();
+			self.cache.insert(key, image, budget);
+		}
+	}
+
+	// A decoder thread is done, upload if it's still wanted
+	fn decoded(&mut self, generation: u64, result: Result<utils::Decoded, error::LoadError>) {
+		let (key, current) = match self.loader.finish(generation) {
+			Some(job) => job,
+			None => return,
+		};
+		let source = &self.playlist.sources()[key.0];
+
+		// Prefetched, errors show up once the image is opened
+		if !current {
+			let near = self.wanted_soon().contains(&key);
+			if let Ok(decoded) = result {
+				let budget = self.cache_budget();
+				let needed: usize = decoded
+					.frames
+					.iter()
+					.map(|(image, _)| texture::ImageTexture::needed_memory(image))
+					.sum();
+				if near && needed <= budget {
+					if let Ok(image) =
+						utils::UiUtils::upload_frames(&self.gl_display, source, decoded)
+					{
+						self.cache.insert(key, image, budget);
+					}
+				}
+			}
+			return;
+		}
+
+		match result
+			.and_then(|decoded| utils::UiUtils::upload_frames(&self.gl_display, source, decoded))
+		{
+			Ok(image) => {
+				// Only the first image sizes the window
+				if self.resize_to_image {
+					let size = image.texture().dimensions();
+					self.gl_display
+						.gl_window()
+						.resize(glium::glutin::dpi::PhysicalSize::from(size));
+				}
+				self.set_image(Some((key, image)));
+				self.prefetch();
+			}
+			Err(e) => {
+				self.set_image(None);
+				self.error = Some(PopupError::Image(e.to_string()));
+			}
+		}
+		self.resize_to_image = false;
+
+		self.gl_display.gl_window().window().request_redraw();
+	}
+
+	// Show the preview until the full image is done, if it isn't already
+	fn previewed(&mut self, generation: u64, decoded: utils::Decoded) {
+		if !self.loader.is_waiting_for(generation) {
+			return;
+		}
+		let source = self.playlist.current();
+		let key = (self.playlist.index(), self.page);
+
+		// Errors show up once the full image fails too
+		if let Ok(image) = utils::UiUtils::upload_frames(&self.gl_display, source, decoded) {
+			if self.resize_to_image {
+				let size = image.texture().dimensions();
+				self.gl_display
+					.gl_window()
+					.resize(glium::glutin::dpi::PhysicalSize::from(size));
+				self.resize_to_image = false;
+			}
+			self.set_image(Some((key, image)));
+			self.gl_display.gl_window().window().request_redraw();
+		}
+	}
+
+	// Rasterize an SVG again once it's zoomed in past its last raster
+	// The old one stays on screen until the new one is done
+	fn sharpen(&mut self) {
+		let (key, image) = match (self.image_key, &mut self.image) {
+			(Some(key), Some(image)) => (key, image),
+			_ => return,
+		};
+		let vector = match &image.vector {
+			Some(vector) => vector,
+			None => return,
+		};
+
+		let (width, height) = self.gl_display.get_framebuffer_dimensions();
+		let texel_size = self
+			.view
+			.texel_size(image.texture().dimensions(), (width as f32, height as f32));
+		let scale = vector.scale_for(texel_size);
+		let running = self
+			.rasterizing
+			.is_some_and(|(running, running_scale)| running == key && scale <= running_scale);
+		if scale <= image.raster_scale || running {
+			return;
+		}
+		self.rasterizing = Some((key, scale));
+
+		let vector = vector.clone();
+		let options = self.decode.clone();
+		let proxy = self.proxy.clone();
+		std::thread::Builder::new()
+			.name("rasterizer".to_string())
+			.spawn(move || {
+				// Can't fail below MAX_SIDE, the old raster just stays if it does
+				if let Some(pixels) = utils::UiUtils::rasterize(&vector, scale, &options) {
+					proxy
+						.send_event(UserEvent::Rasterized(key, scale, pixels))
+						.ok();
+				}
+			})
+			.unwrap();
+	}
+
+	// A sharper raster is done, used if the image is still on screen
+	fn rasterized(&mut self, key: loader::ImageKey, scale: f32, pixels: image::DynamicImage) {
+		// Only the newest one, older ones were overtaken by zooming in further
+		if self.rasterizing != Some((key, scale)) {
+			return;
+		}
+		self.rasterizing = None;
+		let image = match &mut self.image {
+			Some(image) if self.image_key == Some(key) => image,
+			_ => return,
+		};
+
+		let size = image.texture().dimensions();
+		let source = &self.playlist.sources()[key.0];
+		match utils::UiUtils::upload_texture(&self.gl_display, source, pixels) {
+			Ok(mut texture) => {
+				texture.stretch(size.0, size.1);
+				image.replace_texture(texture);
+				image.raster_scale = scale;
+			}
+			// Out of video memory is likely, the smaller raster still works
+			Err(e) => {
+				let e = format!("Can't sharpen the image, the current one stays: {}", e);
+				self.error.get_or_insert(PopupError::Other(e));
+			}
+		}
+
+		self.gl_display.gl_window().window().request_redraw();
+	}
+
+	// Watcher that sends ConfigChanged, errors are for the popup
+	fn watch_config(
+		path: &std::path::Path,
+		proxy: &glium::glutin::event_loop::EventLoopProxy<UserEvent>,
+	) -> Result<(notify::RecommendedWatcher, std::path::PathBuf), String> {
+		let proxy = proxy.clone();
+		settings::watch(path, move || {
+			proxy.send_event(UserEvent::ConfigChanged).ok();
+		})
+		.map_err(|e| {
+			format!(
+				"Can't watch `{}` for changes, restart to apply them: {}",
+				path.display(),
+				e
+			)
+		})
+	}
+
+	// Apply a changed config file to the running window
+	// Settings that only matter on start up, e.g. menus, stay as they are
+	fn reload_settings(&mut self) {
+		let path = match &self.config_path {
+			Some(path) => path.clone(),
+			None => return,
+		};
+
+		// A directory on the way to the config was made, watch closer to it
+		let closer = self
+			.config_watcher
+			.as_ref()
+			.is_some_and(|(_, dir)| *dir != settings::config_dir(&path));
+		if closer {
+			match WindowData::watch_config(&path, &self.proxy) {
+				Ok(watcher) => self.config_watcher = Some(watcher),
+				Err(e) => self.error = Some(PopupError::Other(e)),
+			}
+		}
+
+		// Editors remove the file for a moment while saving
+		if !path.exists() {
+			return;
+		}
+
+		match settings::Settings::read(&path) {
+			Ok(settings) => {
+				let decode = match utils::DecodeOptions::new(&settings.image) {
+					Ok(decode) => decode,
+					Err(e) => {
+						self.error = Some(PopupError::Other(e));
+						self.gl_display.gl_window().window().request_redraw();
+						return;
+					}
+				};
+				let old = &self.settings.image;
+				let redecode = settings.image.auto_orient != old.auto_orient
+					|| settings.image.color_management != old.color_management
+					|| settings.image.display_profile != old.display_profile;
+
+				// Live changes are kept unless the config changes them too
+				if settings.hdr != self.settings.hdr {
+					self.hdr = settings.hdr.clone();
+				}
+
+				self.bindings = actions::Bindings::new(&settings.keybindings).unwrap_or_default();
+				self.settings = settings;
+				self.decode = decode;
+				self.apply_theme();
+
+				if redecode {
+					// Every decoded image is turned or colored the wrong way now
+					self.loader.forget();
+					self.rasterizing = None;
+					self.cache = Default::default();
+					self.image = None;
+					self.image_key = None;
+					self.open_current();
+				} else {
+					// The cache settings might have changed
+					let budget = self.cache_budget();
+					self.cache.shrink(budget);
+					self.prefetch();
+				}
+				self.gl_display
+					.gl_window()
+					.window()
+					.set_title(&WindowData::title(&self.settings, &self.playlist));
+			}
+			// Keep the old settings until the file is fixed
+			Err(e) => self.error = Some(PopupError::Other(e)),
+		}
+
+		self.gl_display.gl_window().window().request_redraw();
+	}
+
+	// Keep zoom, pan and rotation of the image that is on screen
+	fn remember_view(&mut self) {
+		let (path, page) = match (&self.shown, self.settings.session.remember_view) {
+			(Some((path, page)), true) => (path, *page),
+			_ => return,
+		};
+
+		// Fitted is the default, no need to keep it
+		let fitted = self.view.zoom_level == 1.0
+			&& self.view.offset == (0.0, 0.0)
+			&& self.view.rotation == 0;
+		let view = (!fitted).then(|| state::ViewState {
+			path: path.clone(),
+			page,
+			zoom: self.view.zoom_level,
+			offset: self.view.offset,
+			rotation: self.view.rotation,
+		});
+
+		self.state.set_view(path, page, view);
+	}
+
+	// Write what should be restored on the next start
+	fn save_state(&mut self) {
+		if !self.settings.session.remember {
+			return;
+		}
+
+		self.remember_view();
+
+		let window = self.gl_display.gl_window();
+		let window = window.window();
+		if window.fullscreen().is_none() {
+			let size = window.inner_size();
+			let position = window.outer_position().ok();
+			self.state.window = Some(state::WindowState {
+				width: size.width,
+				height: size.height,
+				x: position.map(|position| position.x),
+				y: position.map(|position| position.y),
+			});
+		}
+
+		// Stdin can't be opened again
+		if let Some(path) = self.playlist.current().path() {
+			let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+			self.state.last_directory = path.parent().map(|dir| dir.to_path_buf());
+			self.state.last_file = Some(path);
+		}
+
+		self.state.menus = Some(state::MenuState {
+			debug: self.debug_menu,
+			metadata: self.metadata_menu,
+			buttons: self.action_menu,
+		});
+
+		if let Err(e) = self.state.save() {
+			eprintln!("{}", e);
+		}
+	}
+
+	// Style and font from settings.theme
+	fn apply_theme(&mut self) {
+		theme::apply_style(
+			self.im_builder.style_mut(),
+			&self.base_style,
+			&self.settings.theme,
 		);
 
-		let offset_x =
-		(image_size_px.0 * self.zoom_level + self.offset.0) / (image_size_px.0 * self.zoom_level);
+		// The font size is baked into the font texture
+		{
+			let mut fonts = self.im_builder.fonts();
+			fonts.clear();
+			fonts.add_font(&[theme::font(&self.settings.theme)]);
+		}
+		if let Err(e) = self.im_renderer.reload_font_texture(&mut self.im_builder) {
+			eprintln!("Can't reload the UI font: {:?}", e);
+		}
+	}
+
+	// Everything the keyboard and the buttons can do
+	fn run_action(&mut self, action: actions::Action) {
+		match action {
+			// Cancels loading first, the next one quits
+			actions::Action::Quit => match self.loader.cancel() {
+				Some(name) => {
+					self.set_image(None);
+					self.error = Some(PopupError::Image(format!(
+						"Loading `{}` was cancelled",
+						name
+					)));
+				}
+				None => self.quit = true,
+			},
+			actions::Action::NextImage
+			| actions::Action::PreviousImage
+			| actions::Action::FirstImage
+			| actions::Action::LastImage => {
+				match action {
+					actions::Action::NextImage => self.playlist.next(),
+					actions::Action::PreviousImage => self.playlist.previous(),
+					actions::Action::FirstImage => self.playlist.first(),
+					_ => self.playlist.last(),
+				}
+
+				// Don't reload if there's nothing to flip to
+				if self.playlist.len() > 1 {
+					self.page = 0;
+					self.open_current();
+				}
+			}
+			// Stops at the first and last page
+			actions::Action::NextPage | actions::Action::PreviousPage => {
+				let page = match action {
+					actions::Action::NextPage => (self.page + 1).min(self.pages() - 1),
+					_ => self.page.saturating_sub(1),
+				};
+				if page != self.page {
+					self.page = page;
+					self.open_current();
+				}
+			}
+			actions::Action::ZoomIn => {
+				self.view.zoom_level = (self.view.zoom_level * 1.2).clamp(0.01, 100.0)
+			}
+			actions::Action::ZoomOut => {
+				self.view.zoom_level = (self.view.zoom_level / 1.2).clamp(0.01, 100.0)
+			}
+			actions::Action::ResetView => {
+				self.view.offset = (0.0, 0.0);
+				self.view.zoom_level = 1.0;
+				self.view.rotation = 0;
+				self.last_offset = (0.0, 0.0);
+			}
+			actions::Action::RotateCw => self.view.rotate(1),
+			actions::Action::RotateCcw => self.view.rotate(-1),
+			actions::Action::PauseAnimation => {
+				if let Some(image) = &mut self.image {
+					image.set_paused(!image.paused());
+				}
+			}
+			actions::Action::NextFrame | actions::Action::PreviousFrame => {
+				let step = if action == actions::Action::NextFrame {
+					1
+				} else {
+					-1
+				};
+				if let Some(image) = &mut self.image {
+					image.step(step);
+				}
+			}
+			actions::Action::FasterAnimation => {
+				self.animation_speed =
+					(self.animation_speed * 2.0).min(animation::Animation::MAX_SPEED)
+			}
+			actions::Action::SlowerAnimation => {
+				self.animation_speed =
+					(self.animation_speed / 2.0).max(animation::Animation::MIN_SPEED)
+			}
+			actions::Action::ExposureUp | actions::Action::ExposureDown => {
+				let step = if action == actions::Action::ExposureUp {
+					0.5
+				} else {
+					-0.5
+				};
+				let max = settings::HdrSettings::MAX_EXPOSURE;
+				self.hdr.exposure = (self.hdr.exposure + step).clamp(-max, max);
+			}
+			actions::Action::GammaUp | actions::Action::GammaDown => {
+				let step = if action == actions::Action::GammaUp {
+					0.1
+				} else {
+					-0.1
+				};
+				self.hdr.gamma = (self.hdr.gamma + step).clamp(
+					settings::HdrSettings::MIN_GAMMA,
+					settings::HdrSettings::MAX_GAMMA,
+				);
+			}
+			actions::Action::NextToneMapping => {
+				self.hdr.tone_mapping = self.hdr.tone_mapping.next()
+			}
+			actions::Action::ResetHdr => self.hdr = self.settings.hdr.clone(),
+			actions::Action::ToggleFullscreen => {
+				let window = self.gl_display.gl_window();
+				let window = window.window();
+				window.set_fullscreen(match window.fullscreen() {
+					Some(_) => None,
+					None => Some(glium::glutin::window::Fullscreen::Borderless(None)),
+				});
+			}
+			actions::Action::ToggleButtons => self.action_menu = !self.action_menu,
+			actions::Action::ToggleDebug => self.debug_menu = !self.debug_menu,
+			actions::Action::ToggleMetadata => self.metadata_menu = !self.metadata_menu,
+			actions::Action::ToggleExample => self.example_menu = !self.example_menu,
+		}
 
-		let offset_y = 
-		(image_size_px.1 * self.zoom_level + self.offset.1) / (image_size_px.1 * self.zoom_level);
+		self.gl_display.gl_window().window().request_redraw();
+	}
 
-		let transform = Matrix4::from_translation(cgmath::Vector3::new(
-			offset_x - 1.0,
-			-(offset_y - 1.0),
-			0.0,
-		)) * transform;
-		
-		// Zoom
-		let transform = Matrix4::from_scale(self.zoom_level) * transform;
+	fn error_action(&mut self, action: ErrorAction) {
+		match action {
+			ErrorAction::Retry => self.open_current(),
+			ErrorAction::Skip => {
+				self.playlist.next();
+				self.page = 0;
+				self.open_current();
+			}
+			ErrorAction::Dismiss => self.error = None,
+			ErrorAction::Open(index) => {
+				self.playlist.select(index);
+				self.page = 0;
+				self.open_current();
+			}
+		}
 
-		Into::<[[f32; 4]; 4]>::into(transform)
+		self.gl_display.gl_window().window().request_redraw();
 	}
 
-	fn new(filename: String) -> (WindowData, glium::glutin::event_loop::EventLoop<()>) {
+	fn new(
+		playlist: playlist::Playlist,
+		args: &cli::Args,
+		settings: settings::Settings,
+		decode: utils::DecodeOptions,
+		config_path: Option<std::path::PathBuf>,
+		state: state::State,
+	) -> (WindowData, glium::glutin::event_loop::EventLoop<UserEvent>) {
 		// Default window size
 		let width = 800i32;
 		let height = 600i32;
 
-		// Set title settings::Settings::WINDOW_TITLE
-		// Holy shit is this ever cursed
-		let title = format!(
-			"{} - {}",
-			settings::WindowSettings::WINDOW_TITLE,
-			std::path::Path::new(&filename)
-				.file_name()
-				.unwrap_or_default()
-				.to_str()
-				.unwrap_or_default()
-		);
+		// Last window, `--fullscreen` wins over it
+		let last_window = state.window.filter(|_| !args.fullscreen);
+
+		let title = WindowData::title(&settings, &playlist);
 
 		// Create OpenGL window
-		let event_loop = glium::glutin::event_loop::EventLoop::new();
-		let window_builder = glium::glutin::window::WindowBuilder::new()
+		let event_loop = glium::glutin::event_loop::EventLoop::with_user_event();
+		let mut window_builder = glium::glutin::window::WindowBuilder::new()
 			.with_title(title)
 			.with_decorations(true)
 			.with_resizable(true)
 			.with_visible(true)
-			.with_inner_size(glium::glutin::dpi::LogicalSize::new(width, height));
+			.with_inner_size(glium::glutin::dpi::LogicalSize::new(width, height))
+			.with_fullscreen(if args.fullscreen {
+				Some(glium::glutin::window::Fullscreen::Borderless(None))
+			} else {
+				None
+			});
+		if let Some(window) = last_window {
+			window_builder = window_builder.with_inner_size(glium::glutin::dpi::PhysicalSize::new(
+				window.width,
+				window.height,
+			));
+		}
 		let context_builder = glium::glutin::ContextBuilder::new()
 			.with_vsync(false) // !Vsync is broken!
 			.with_hardware_acceleration(Some(true))
 			.with_multisampling(2)
 			.with_depth_buffer(0);
-		let display = glium::Display::new(window_builder, context_builder, &event_loop).unwrap();
+		let display = match glium::Display::new(window_builder, context_builder, &event_loop) {
+			Ok(display) => display,
+			Err(e) => {
+				eprintln!("Can't create an OpenGL window: {}", e);
+				std::process::exit(cli::ExitCodes::OPENGL)
+			}
+		};
+		if let Some(state::WindowState {
+			x: Some(x),
+			y: Some(y),
+			..
+		}) = last_window
+		{
+			display
+				.gl_window()
+				.window()
+				.set_outer_position(glium::glutin::dpi::PhysicalPosition::new(x, y));
+		}
 
 		// Create ImGui
 		let mut imgui_builder = imgui::Context::create();
 
 		// Theme
 		imgui_builder.set_ini_filename(None);
-		imgui_builder.style_mut().use_dark_colors();
-		imgui_builder.style_mut().window_rounding = 0.0;
-		imgui_builder.style_mut().window_border_size = 1.0;
-		imgui_builder.style_mut().alpha = 0.9;
-		imgui_builder.style_mut().window_padding = [2.0, 2.0];
-		imgui_builder.style_mut().window_title_align = [1.0, 0.5];
+		let base_style = *imgui_builder.style();
+		theme::apply_style(imgui_builder.style_mut(), &base_style, &settings.theme);
+		imgui_builder
+			.fonts()
+			.add_font(&[theme::font(&settings.theme)]);
 
 		// Make renderer
 		let imgui_renderer =
-			imgui_glium_renderer::Renderer::init(&mut imgui_builder, &display).unwrap();
+			match imgui_glium_renderer::Renderer::init(&mut imgui_builder, &display) {
+				Ok(renderer) => renderer,
+				Err(e) => {
+					eprintln!("Can't create the UI renderer: {}", e);
+					std::process::exit(cli::ExitCodes::OPENGL)
+				}
+			};
 
-		// Get image
-		let image = utils::UiUtils::load_texture(&display, filename).unwrap();
+		// Watch the config, even before it or its directory exist
+		let config_watcher = config_path
+			.as_ref()
+			.map(|path| WindowData::watch_config(path, &event_loop.create_proxy()));
 
-		// Auto resize image
-		display
-			.gl_window()
-			.resize(glium::glutin::dpi::PhysicalSize::new(
-				image.get_width(),
-				image.get_height().unwrap(),
-			));
+		// Already checked when the settings were loaded
+		let bindings = actions::Bindings::new(&settings.keybindings).unwrap_or_default();
+
+		let mut data = WindowData {
+			playlist,
+			loader: Default::default(),
+			proxy: event_loop.create_proxy(),
+			// Auto resize image, unless the last window size is restored
+			resize_to_image: !args.fullscreen && last_window.is_none(),
+			last_draw: std::time::Instant::now(),
+			image: None,
+			image_key: None,
+			page: 0,
+			rasterizing: None,
+			cache: Default::default(),
+			animation_speed: 1.0,
+			hdr: settings.hdr.clone(),
+			gl_display: display,
+			im_builder: imgui_builder,
+			im_renderer: imgui_renderer,
+			base_style,
+			debug_menu: state
+				.menus
+				.map_or(settings.window.debug_menu_open, |menus| menus.debug),
+			example_menu: false,
+			metadata_menu: state
+				.menus
+				.map_or(settings.window.metadata_menu_open, |menus| menus.metadata),
+			// No setting for this because it should always be on, but it's remembered
+			action_menu: state.menus.is_none_or(|menus| menus.buttons),
+			view: view::View::new(args.fit, args.zoom, (0.0, 0.0)),
+			last_offset: (-100000.0, -100000.0),
+			error: None,
+			gl_error: None,
+			state,
+			shown: None,
+			settings,
+			decode,
+			config_path,
+			config_watcher: None,
+			bindings,
+			modifiers: Default::default(),
+			quit: false,
+		};
+
+		// Get image, the window shows up before it's decoded
+		data.open_current();
+		// After opening, that clears the popup
+		match config_watcher {
+			Some(Ok(watcher)) => data.config_watcher = Some(watcher),
+			Some(Err(e)) => data.error = Some(PopupError::Other(e)),
+			None => {}
+		}
+		// --zoom is for a fresh view, a remembered one keeps its own
+		let restored = data.settings.session.remember_view
+			&& data
+				.shown
+				.as_ref()
+				.is_some_and(|(path, page)| data.state.view(path, *page).is_some());
+		if !restored {
+			data.view.zoom_level = args.zoom;
+		}
 
 		// Return data
-		(
-			WindowData {
-				image_texture: Some(image),
-				gl_display: display,
-				uniform: [
-					[1.0, 0.0, 0.0, 0.0],
-					[0.0, 1.0, 0.0, 0.0],
-					[0.0, 0.0, 1.0, 0.0],
-					[0.0, 0.0, 0.0, 1.0f32],
-				],
-				im_builder: imgui_builder,
-				im_renderer: imgui_renderer,
-				debug_menu: settings::WindowSettings::DEBUG_MENU_OPEN,
-				example_menu: false,
-				metadata_menu: settings::WindowSettings::METADATA_MENU_OPEN,
-				action_menu: true, // No setting for this because it should always be on
-				zoom_level: 1.0,
-				offset: (0.0, 0.0),
-				last_offset: (-100000.0, -100000.0),
-			},
-			event_loop,
-		)
+		(data, event_loop)
 	}
 
 	fn draw(&mut self) {
+		self.sharpen();
+
 		// Create render target
 		let mut target = self.gl_display.draw();
 
 		// *Draw background
 		{
 			// Background
-			glium::Surface::clear_color(&mut target, 0.05, 0.05, 0.05, 1.0);
+			let [r, g, b, a] = self.settings.window.background_color;
+			glium::Surface::clear_color(&mut target, r, g, b, a);
 		}
 
 		// *Draw image and quad
-		{
-			// Make quad
-			let vertex_buffer =
-				{ glium::VertexBuffer::new(&self.gl_display, &utils::UiUtils::QUAD).unwrap() };
-			let index_buffer = glium::IndexBuffer::new(
-				&self.gl_display,
-				glium::index::PrimitiveType::TriangleStrip,
-				&[1 as u16, 2, 0, 3],
-			)
-			.unwrap();
-
-			//Calculate uniform
-			let size = self.gl_display.gl_window().window().inner_size();
-			self.uniform = self.calculate_uniform(size.width as f32, size.height as f32);
-
-			let sample = if self.texel_size() >= 6.0 {
-				self.image_texture
-					.as_ref()
-					.unwrap()
-					.sampled()
-					.wrap_function(glium::uniforms::SamplerWrapFunction::BorderClamp)
-					.magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest)
-			} else {
-				self.image_texture
-					.as_ref()
-					.unwrap()
-					.sampled()
-					.wrap_function(glium::uniforms::SamplerWrapFunction::BorderClamp)
-					.magnify_filter(glium::uniforms::MagnifySamplerFilter::Linear)
-			};
-
-			let uniforms = glium::uniform! {
-				matrix: self.uniform,
-				tex: sample,
-			};
-
-			// Get shader
-			let (vertex_shader, fragment_shader) =
-				shaders::get_shader(self.gl_display.get_opengl_version());
-
-			// Create program
-			let program = glium::Program::from_source(
-				&self.gl_display,
-				vertex_shader.as_str(),
-				fragment_shader.as_str(),
-				None,
-			)
-			.unwrap();
-
-			// Draw the quad
-			glium::Surface::draw(
-				&mut target,
-				&vertex_buffer,
-				&index_buffer,
-				&program,
-				&uniforms,
-				&glium::DrawParameters {
-					blend: glium::Blend::alpha_blending(),
-					dithering: true,
-					backface_culling: glium::BackfaceCullingMode::CullingDisabled,
-					..Default::default()
-				},
-			)
-			.unwrap();
+		let drawn = self.image.as_ref().map(|image| {
+			self.view
+				.draw(&self.gl_display, &mut target, image.texture(), &self.hdr)
+		});
+		if let Some(Err(e)) = drawn {
+			self.gl_failed(e);
 		}
 
 		// *Draw ImGui
@@ -280,7 +899,9 @@ impl WindowData {
 			// ImGui IO
 			let framerate = self.im_builder.io().framerate;
 			let delta = self.im_builder.io().delta_time;
-			let mut imgui_io = self.im_builder.io_mut();
+			let cache_budget = self.cache_budget();
+			let (page, pages) = (self.page, self.pages());
+			let imgui_io = self.im_builder.io_mut();
 
 			// Set display dimentions
 			let (width, height) = self.gl_display.get_framebuffer_dimensions();
@@ -288,15 +909,47 @@ impl WindowData {
 
 			// Make a frame
 			let ui = self.im_builder.frame();
+			let scale = self.settings.theme.ui_scale;
+
+			// Same code as the keyboard, so it runs after the UI is done
+			let mut button_action = None;
+			let keybindings = &self.settings.keybindings;
 
 			// Buttons
 			if self.action_menu {
+				// (frame, frame count, paused) of animations
+				let animation = self
+					.image
+					.as_ref()
+					.filter(|image| image.is_animated())
+					.map(|image| (image.frame(), image.frame_count(), image.paused()));
+				let mut buttons_width = 350.0;
+				if animation.is_some() {
+					buttons_width += 250.0;
+				}
+				if pages > 1 {
+					buttons_width += 160.0;
+				}
+				// Exposure, gamma and tone mapping of float images
+				let hdr = self
+					.image
+					.as_ref()
+					.filter(|image| image.texture().hdr)
+					.map(|_| self.hdr.clone());
+				if hdr.is_some() {
+					buttons_width += 330.0;
+				}
+				let speed = self.animation_speed;
+
 				imgui::Window::new(imgui::im_str!("Buttons"))
-					.size([350.0, 32.0], imgui::Condition::FirstUseEver)
+					.size(
+						[buttons_width * scale, 32.0 * scale],
+						imgui::Condition::Always,
+					)
 					.position(
 						[
-							(width as f32 / 2.0) - (350.0 / 2.0),
-							height as f32 - 10.0 - 32.0,
+							(width as f32 / 2.0) - (buttons_width * scale / 2.0),
+							height as f32 - (10.0 + 32.0) * scale,
 						],
 						imgui::Condition::FirstUseEver,
 					)
@@ -310,32 +963,73 @@ impl WindowData {
 					.title_bar(false)
 					.build(&ui, || {
 						ui.separator();
-						ui.same_line_with_spacing(0.0, 5.0);
-						if ui.button(imgui::im_str!("1/1"), [32.0, 32.0]) {
-							self.offset = (0.0, 0.0);
-							self.zoom_level = 1.0;
-							self.last_offset = (0.0, 0.0);
-							self.gl_display.gl_window().window().request_redraw();
+						let mut buttons = vec![
+							(imgui::im_str!("1/1"), actions::Action::ResetView),
+							(imgui::im_str!("-"), actions::Action::ZoomOut),
+							(imgui::im_str!("+"), actions::Action::ZoomIn),
+							(imgui::im_str!("R"), actions::Action::RotateCw),
+							(imgui::im_str!("D"), actions::Action::ToggleDebug),
+							(imgui::im_str!("M"), actions::Action::ToggleMetadata),
+						];
+						if let Some((_, _, paused)) = animation {
+							let pause = if paused {
+								imgui::im_str!(">")
+							} else {
+								imgui::im_str!("||")
+							};
+							buttons.extend([
+								(imgui::im_str!("<<"), actions::Action::SlowerAnimation),
+								(imgui::im_str!("|<"), actions::Action::PreviousFrame),
+								(pause, actions::Action::PauseAnimation),
+								(imgui::im_str!(">|"), actions::Action::NextFrame),
+								(imgui::im_str!(">>"), actions::Action::FasterAnimation),
+							]);
 						}
-						ui.same_line_with_spacing(0.0, 5.0);
-						if ui.button(imgui::im_str!("-"), [32.0, 32.0]) {
-							self.zoom_level /= 1.2;
-							self.gl_display.gl_window().window().request_redraw();
+						if pages > 1 {
+							buttons.extend([
+								(imgui::im_str!("^"), actions::Action::PreviousPage),
+								(imgui::im_str!("v"), actions::Action::NextPage),
+							]);
 						}
-						ui.same_line_with_spacing(0.0, 5.0);
-						if ui.button(imgui::im_str!("+"), [32.0, 32.0]) {
-							self.zoom_level *= 1.2;
-							self.gl_display.gl_window().window().request_redraw();
+						if hdr.is_some() {
+							buttons.extend([
+								(imgui::im_str!("E-"), actions::Action::ExposureDown),
+								(imgui::im_str!("E+"), actions::Action::ExposureUp),
+								(imgui::im_str!("T"), actions::Action::NextToneMapping),
+							]);
 						}
-						ui.same_line_with_spacing(0.0, 5.0);
-						if ui.button(imgui::im_str!("D"), [32.0, 32.0]) {
-							self.debug_menu = !self.debug_menu;
-							self.gl_display.gl_window().window().request_redraw();
+
+						for (label, action) in buttons {
+							ui.same_line_with_spacing(0.0, 5.0);
+							if ui.button(label, [32.0 * scale, 32.0 * scale]) {
+								button_action = Some(action);
+							}
+							if ui.is_item_hovered() {
+								ui.tooltip_text(format!(
+									"{} ({})",
+									action.description(),
+									keybindings.keys(action)
+								));
+							}
+						}
+
+						// Frame counter and speed
+						if let Some((frame, count, _)) = animation {
+							ui.same_line_with_spacing(0.0, 5.0);
+							ui.text(format!("{}/{} {}x", frame + 1, count, speed));
+						}
+						if pages > 1 {
+							ui.same_line_with_spacing(0.0, 5.0);
+							ui.text(format!("page {}/{}", page + 1, pages));
 						}
-						ui.same_line_with_spacing(0.0, 5.0);
-						if ui.button(imgui::im_str!("M"), [32.0, 32.0]) {
-							self.metadata_menu = !self.metadata_menu;
-							self.gl_display.gl_window().window().request_redraw();
+						if let Some(hdr) = &hdr {
+							ui.same_line_with_spacing(0.0, 5.0);
+							ui.text(format!(
+								"{:+.1} EV, gamma {:.1}, {}",
+								hdr.exposure,
+								hdr.gamma,
+								hdr.tone_mapping.name()
+							));
 						}
 					});
 			}
@@ -343,7 +1037,8 @@ impl WindowData {
 			// Debug window
 			if self.debug_menu {
 				imgui::Window::new(imgui::im_str!("Debug"))
-					.size([350.0, 100.0], imgui::Condition::FirstUseEver)
+					// Fits any font size
+					.always_auto_resize(true)
 					.position(
 						[(width as f32 / 2f32) - (ui.window_size()[0] / 2.0), 10.0],
 						imgui::Condition::Always,
@@ -364,16 +1059,85 @@ impl WindowData {
 								.get_free_video_memory()
 								.unwrap_or(usize::MIN) / 1_000_000
 						));
+						if let Some(texture) = self.image.as_ref().map(|image| image.texture()) {
+							ui.text(format!(
+								"Texture: {} {}x{}",
+								texture.format_name,
+								texture.dimensions().0,
+								texture.dimensions().1
+							));
+							if texture.tiles.len() > 1 {
+								ui.text(format!("Tiles: {}", texture.tiles.len()));
+							}
+						}
+						ui.text(format!(
+							"Cache: {} images, {}/{}MB",
+							self.cache.len(),
+							self.cache.memory() / 1_000_000,
+							cache_budget / 1_000_000
+						));
 						ui.text(format!("Reported FPS: {}", framerate));
 						ui.text(format!("Delta: {}", delta));
 						ui.text(format!("Calculated FPS: {}", 1.0 / delta));
 					});
 			}
 
+			// Metadata window
+			if self.metadata_menu {
+				imgui::Window::new(imgui::im_str!("Metadata"))
+					.always_auto_resize(true)
+					.position(
+						[width as f32 - 10.0 * scale, 10.0 * scale],
+						imgui::Condition::Always,
+					)
+					.position_pivot([1.0, 0.0])
+					.scrollable(false)
+					.collapsible(false)
+					.no_decoration()
+					.scroll_bar(false)
+					.resizable(false)
+					.title_bar(false)
+					.build(&ui, || {
+						ui.text(self.playlist.current().name());
+						ui.separator();
+						match &self.image {
+							Some(image) => {
+								let (width, height) = image.texture().dimensions();
+								ui.text(format!("Dimensions: {}x{}", width, height));
+								if image.preview {
+									ui.text_disabled("Preview, still decoding");
+								}
+								if image.is_animated() {
+									ui.text(format!("Frames: {}", image.frame_count()));
+								}
+								if image.pages > 1 {
+									ui.text(format!("Pages: {}", image.pages));
+								}
+								ui.text(format!(
+									"Color profile: {}",
+									image.profile.as_deref().unwrap_or("none, shown as sRGB")
+								));
+								if image.texture().hdr {
+									ui.text(format!(
+										"HDR: {:+.1} EV, gamma {:.1}, {}",
+										self.hdr.exposure,
+										self.hdr.gamma,
+										self.hdr.tone_mapping.name()
+									));
+								}
+							}
+							None => ui.text_disabled("No image"),
+						}
+					});
+			}
+
 			// Example window
 			if self.example_menu {
 				imgui::Window::new(imgui::im_str!("Test window"))
-					.size([300.0, 100.0], imgui::Condition::FirstUseEver)
+					.size(
+						[300.0 * scale, 100.0 * scale],
+						imgui::Condition::FirstUseEver,
+					)
 					.build(&ui, || {
 						ui.text("Hello world!");
 						ui.text("This...is...r-liv!");
@@ -386,93 +1150,227 @@ impl WindowData {
 					});
 			}
 
+			// Loading indicator, a splash screen while there is no image yet
+			if let Some((name, elapsed)) = self.loader.loading() {
+				let dots = ".".repeat(1 + (elapsed.as_millis() / 300 % 3) as usize);
+				let splash = self.image.is_none();
+				let (position, pivot) = if splash {
+					([width as f32 / 2.0, height as f32 / 2.0], [0.5, 0.5])
+				} else {
+					([10.0 * scale, 10.0 * scale], [0.0, 0.0])
+				};
+
+				imgui::Window::new(imgui::im_str!("Loading"))
+					.position(position, imgui::Condition::Always)
+					.position_pivot(pivot)
+					.always_auto_resize(true)
+					.no_decoration()
+					.no_inputs()
+					.build(&ui, || {
+						if splash {
+							ui.text(&self.settings.window.title);
+							ui.separator();
+						}
+						ui.text(format!("Loading {}{}", name, dots));
+						ui.text_disabled(format!(
+							"{} to cancel",
+							self.settings.keybindings.keys(actions::Action::Quit)
+						));
+					});
+			}
+
+			// Error popup
+			let mut error_action = None;
+			if let Some(error) = &self.error {
+				let (error, image) = match error {
+					PopupError::Image(error) => (error, true),
+					PopupError::Other(error) => (error, false),
+				};
+				ui.open_popup(imgui::im_str!("Error"));
+				ui.popup_modal(imgui::im_str!("Error"))
+					.always_auto_resize(true)
+					.movable(false)
+					.resizable(false)
+					.collapsible(false)
+					.build(|| {
+						ui.text_wrapped(&imgui::ImString::new(error));
+						ui.separator();
+
+						if image {
+							if ui.button(imgui::im_str!("Retry"), [80.0 * scale, 24.0 * scale]) {
+								error_action = Some(ErrorAction::Retry);
+							}
+							if self.playlist.len() > 1 {
+								ui.same_line_with_spacing(0.0, 5.0);
+								if ui.button(imgui::im_str!("Skip"), [80.0 * scale, 24.0 * scale]) {
+									error_action = Some(ErrorAction::Skip);
+								}
+							}
+							ui.same_line_with_spacing(0.0, 5.0);
+						}
+						if ui.button(imgui::im_str!("Close"), [80.0 * scale, 24.0 * scale]) {
+							error_action = Some(ErrorAction::Dismiss);
+						}
+
+						// Open another
+						if image && self.playlist.len() > 1 {
+							ui.separator();
+							ui.text("Open another:");
+							imgui::ChildWindow::new("Images")
+								.size([300.0 * scale, 150.0 * scale])
+								.build(&ui, || {
+									for (index, source) in
+										self.playlist.sources().iter().enumerate()
+									{
+										let name = imgui::ImString::new(source.name());
+										if imgui::Selectable::new(&name)
+											.selected(index == self.playlist.index())
+											.build(&ui)
+										{
+											error_action = Some(ErrorAction::Open(index));
+										}
+									}
+								});
+						}
+
+						if error_action.is_some() {
+							ui.close_current_popup();
+						}
+					});
+			}
+
 			// Render that ImGui frame to target
-			self.im_renderer.render(&mut target, ui.render()).unwrap();
+			if let Err(e) = self.im_renderer.render(&mut target, ui.render()) {
+				eprintln!("Can't draw the UI: {}", e);
+			}
+
+			// Needs all of self, so after the UI is done
+			if let Some(action) = error_action {
+				self.error_action(action);
+			}
+			if let Some(action) = button_action {
+				self.run_action(action);
+			}
 		}
 
 		// End
-		target.finish().unwrap();
+		// Shown on the next frame, e.g. after the driver reset the context
+		if let Err(e) = target.finish() {
+			self.gl_failed(format!("Can't show the frame: {}", e));
+			self.gl_display.gl_window().window().request_redraw();
+		}
+		self.last_draw = std::time::Instant::now();
+	}
+
+	// The same error would come every frame while the fault lasts,
+	// so it only shows up once and closing the popup sticks
+	fn gl_failed(&mut self, e: String) {
+		if self.gl_error.as_ref() != Some(&e) {
+			self.gl_error = Some(e.clone());
+			self.error.get_or_insert(PopupError::Other(e));
+		}
 	}
 
-	fn window_loop(mut self, event: glium::glutin::event_loop::EventLoop<()>) {
+	fn window_loop(mut self, event: glium::glutin::event_loop::EventLoop<UserEvent>) {
 		// Loop
 		event.run(move |event, _, control_flow| {
 			let event_ref = &event;
 
 			// Close
-			if let glium::glutin::event::Event::WindowEvent { event, .. } = event_ref {
+			if let glium::glutin::event::Event::WindowEvent {
+				event: glium::glutin::event::WindowEvent::CloseRequested,
+				..
+			} = event_ref
+			{
+				*control_flow = glium::glutin::event_loop::ControlFlow::Exit;
+				return;
+			}
+
+			// Exit, after ControlFlow::Exit
+			if let glium::glutin::event::Event::LoopDestroyed = event_ref {
+				self.save_state();
+				return;
+			}
+
+			// Other threads
+			if let glium::glutin::event::Event::UserEvent(_) = event_ref {
 				match event {
-					glium::glutin::event::WindowEvent::CloseRequested
-					| glium::glutin::event::WindowEvent::KeyboardInput {
-						input:
-							glium::glutin::event::KeyboardInput {
-								virtual_keycode: Some(glium::glutin::event::VirtualKeyCode::Escape),
-								..
-							},
-						..
-					} => {
-						*control_flow = glium::glutin::event_loop::ControlFlow::Exit;
-						return;
+					glium::glutin::event::Event::UserEvent(UserEvent::ConfigChanged) => {
+						self.reload_settings()
 					}
+					glium::glutin::event::Event::UserEvent(UserEvent::Decoded(
+						generation,
+						result,
+					)) => self.decoded(generation, result),
+					glium::glutin::event::Event::UserEvent(UserEvent::Preview(
+						generation,
+						decoded,
+					)) => self.previewed(generation, decoded),
+					glium::glutin::event::Event::UserEvent(UserEvent::Rasterized(
+						key,
+						scale,
+						pixels,
+					)) => self.rasterized(key, scale, pixels),
 					_ => (),
 				}
+				return;
 			}
-			std::mem::drop(event_ref);
 
-			// Menus
+			// Keys
 			if let glium::glutin::event::Event::WindowEvent { event, .. } = event_ref {
 				match event {
-					// If F2 **pressed**
-					glium::glutin::event::WindowEvent::KeyboardInput {
-						input:
-							glium::glutin::event::KeyboardInput {
-								state: glium::glutin::event::ElementState::Pressed,
-								virtual_keycode: Some(glium::glutin::event::VirtualKeyCode::F2),
-								..
-							},
-						..
-					} => {
-						self.debug_menu = !self.debug_menu;
-						self.gl_display.gl_window().window().request_redraw();
-					}
-					// If Home **pressed**
-					glium::glutin::event::WindowEvent::KeyboardInput {
-						input:
-							glium::glutin::event::KeyboardInput {
-								state: glium::glutin::event::ElementState::Pressed,
-								virtual_keycode: Some(glium::glutin::event::VirtualKeyCode::Home),
-								..
-							},
-						..
-					} => {
-						self.example_menu = !self.example_menu;
-						self.gl_display.gl_window().window().request_redraw();
+					glium::glutin::event::WindowEvent::ModifiersChanged(modifiers) => {
+						self.modifiers = *modifiers;
 					}
-					// If space **pressed**
+					// Only **pressed**
 					glium::glutin::event::WindowEvent::KeyboardInput {
 						input:
 							glium::glutin::event::KeyboardInput {
 								state: glium::glutin::event::ElementState::Pressed,
-								virtual_keycode: Some(glium::glutin::event::VirtualKeyCode::Space),
+								virtual_keycode: Some(key),
 								..
 							},
 						..
 					} => {
-						self.action_menu = !self.action_menu;
-						self.gl_display.gl_window().window().request_redraw();
+						let press = actions::KeyPress {
+							key: *key,
+							modifiers: self.modifiers,
+						};
+						if let Some(action) = self.bindings.press(press) {
+							self.run_action(action);
+						}
 					}
 					_ => (),
 				}
 			}
 
-			// Resized
-			if let glium::glutin::event::Event::WindowEvent { event, .. } = event_ref {
-				match event {
-					glium::glutin::event::WindowEvent::Resized(..) => {
-						self.gl_display.gl_window().window().request_redraw();
-					}
-					_ => (),
+			if self.quit {
+				*control_flow = glium::glutin::event_loop::ControlFlow::Exit;
+				return;
+			}
+
+			// Keep the loading indicator moving, but don't take CPU from the decoder
+			if let glium::glutin::event::Event::MainEventsCleared = event_ref {
+				if self.loader.loading().is_some()
+					&& self.last_draw.elapsed() > std::time::Duration::from_millis(100)
+				{
+					self.gl_display.gl_window().window().request_redraw();
 				}
+
+				// Only draws when the next frame is due
+				let speed = self.animation_speed;
+				if self.image.as_mut().is_some_and(|image| image.update(speed)) {
+					self.gl_display.gl_window().window().request_redraw();
+				}
+			}
+
+			// Resized
+			if let glium::glutin::event::Event::WindowEvent {
+				event: glium::glutin::event::WindowEvent::Resized(..),
+				..
+			} = event_ref
+			{
+				self.gl_display.gl_window().window().request_redraw();
 			}
 
 			// Draw
@@ -481,15 +1379,15 @@ impl WindowData {
 			}
 
 			// Set mouse stuff
-			let mut imgui_io = self.im_builder.io_mut();
+			let imgui_io = self.im_builder.io_mut();
 			if let glium::glutin::event::Event::WindowEvent { event, .. } = event_ref {
 				match event {
 					glium::glutin::event::WindowEvent::CursorMoved { position, .. } => {
 						imgui_io.mouse_pos = [position.x as f32, position.y as f32];
 
 						if imgui_io.mouse_down[2] && self.last_offset.0 != -100000.0 {
-							self.offset.0 += (position.x as f32) - self.last_offset.0;
-							self.offset.1 += (position.y as f32) - self.last_offset.1;
+							self.view.offset.0 += (position.x as f32) - self.last_offset.0;
+							self.view.offset.1 += (position.y as f32) - self.last_offset.1;
 						}
 
 						self.last_offset.0 = position.x as f32;
@@ -544,31 +1442,34 @@ impl WindowData {
 							}
 						};
 
-						self.zoom_level *=
-							1.0 + (delta * settings::ImageSettings::ZOOM_MULTIPLIER / 100.0);
+						self.view.zoom_level *=
+							1.0 + (delta * self.settings.image.zoom_multiplier / 100.0);
 
 						// Somehow you can zoom into australia
-						self.zoom_level = self.zoom_level.abs();
+						self.view.zoom_level = self.view.zoom_level.abs();
 
-						self.zoom_level = self.zoom_level.max(
-							0.01
-						).min(
-							100.0
-						);
+						self.view.zoom_level = self.view.zoom_level.clamp(0.01, 100.0);
 
 						self.gl_display.gl_window().window().request_redraw();
 					}
 					_ => (),
 				}
 			}
-			std::mem::drop(imgui_io);
 		});
 	}
 }
 
-pub fn window(filename: &str) {
+// config_path is watched for changes, it doesn't have to exist yet
+pub fn window(
+	playlist: playlist::Playlist,
+	args: &cli::Args,
+	settings: settings::Settings,
+	decode: utils::DecodeOptions,
+	config_path: Option<std::path::PathBuf>,
+	state: state::State,
+) {
 	// Init
-	let (data, event_loop) = WindowData::new(filename.to_string());
+	let (data, event_loop) = WindowData::new(playlist, args, settings, decode, config_path, state);
 
 	// Loop
 	data.window_loop(event_loop);
@@ -1,8 +1,20 @@
 // utils.rs
 // Goal: Take the filth from important classes to simplify the code
 
+extern crate exif;
 extern crate image;
 
+use crate::animation;
+use crate::color;
+use crate::error::LoadError;
+use crate::pages;
+use crate::raw;
+use crate::settings;
+use crate::svg;
+use crate::texture;
+
+use glium::CapabilitiesSource;
+
 // Vertex type
 #[derive(Copy, Clone)]
 pub struct Vertex {
@@ -11,63 +23,640 @@ pub struct Vertex {
 }
 glium::implement_vertex!(Vertex, position, tex_coords);
 
+// What a decoder thread hands back
+#[derive(Debug)]
+pub struct Decoded {
+	// Frames and how long each is shown, still images have one
+	pub frames: Vec<(image::DynamicImage, std::time::Duration)>,
+	// Pages in the file, see pages.rs
+	pub pages: usize,
+	// What happened to the embedded color profile, see color::apply
+	pub profile: Option<String>,
+	// Set on quick previews, size of the full image they stand in for
+	pub preview_of: Option<(u32, u32)>,
+	// SVGs, to rasterize again when zoomed in
+	pub vector: Option<svg::Vector>,
+}
+
+// What happens to the pixels after decoding, the same for every image
+#[derive(Clone)]
+pub struct DecodeOptions {
+	// Turn like the EXIF Orientation tag says
+	pub auto_orient: bool,
+	// Convert embedded color profiles to this, None keeps the stored values
+	pub color: Option<color::Target>,
+}
+
+impl DecodeOptions {
+	// Reads settings.display_profile, so it can fail
+	pub fn new(settings: &settings::ImageSettings) -> Result<DecodeOptions, String> {
+		let color = if !settings.color_management {
+			None
+		} else if settings.display_profile.is_empty() {
+			Some(color::Target::srgb())
+		} else {
+			Some(color::Target::load(std::path::Path::new(
+				&settings.display_profile,
+			))?)
+		};
+
+		Ok(DecodeOptions {
+			auto_orient: settings.auto_orient,
+			color,
+		})
+	}
+}
+
+// Where an image comes from
+#[derive(Clone)]
+pub enum ImageSource {
+	// Image on disk
+	File(std::path::PathBuf),
+	// Bytes that never hit the disk, e.g. stdin
+	// (name, bytes)
+	Memory(String, std::sync::Arc<Vec<u8>>),
+}
+
+impl ImageSource {
+	// Read all of stdin, it can only be read once so keep the bytes
+	pub fn stdin() -> std::io::Result<ImageSource> {
+		let mut bytes = Vec::new();
+		std::io::Read::read_to_end(&mut std::io::stdin().lock(), &mut bytes)?;
+
+		Ok(ImageSource::Memory(
+			"stdin".to_string(),
+			std::sync::Arc::new(bytes),
+		))
+	}
+
+	// Stdin is at its end without anything in it, e.g. `</dev/null` or a desktop launcher
+	// Only peeks, stdin() still gets every byte
+	pub fn stdin_is_empty() -> bool {
+		std::io::BufRead::fill_buf(&mut std::io::stdin().lock())
+			.map_or(true, |bytes| bytes.is_empty())
+	}
+
+	// Name to show the user
+	pub fn name(&self) -> String {
+		match self {
+			ImageSource::File(path) => path
+				.file_name()
+				.unwrap_or_default()
+				.to_string_lossy()
+				.to_string(),
+			ImageSource::Memory(name, _) => name.clone(),
+		}
+	}
+
+	pub fn path(&self) -> Option<&std::path::Path> {
+		match self {
+			ImageSource::File(path) => Some(path),
+			ImageSource::Memory(..) => None,
+		}
+	}
+
+	// Whole file, decoders and metadata readers all work on the same bytes
+	pub fn bytes(&self) -> std::io::Result<std::sync::Arc<Vec<u8>>> {
+		match self {
+			ImageSource::File(path) => Ok(std::sync::Arc::new(std::fs::read(path)?)),
+			ImageSource::Memory(_, bytes) => Ok(bytes.clone()),
+		}
+	}
+}
+
 // Utilities for ui.rs
 pub struct UiUtils {}
 impl UiUtils {
-	// Read image file
-	// replaces image::open(name);
-	pub fn load_texture(
-		display: &glium::Display,
-		filename: String,
-	) -> Result<glium::texture::SrgbTexture2d, u8> {
-		let name = &filename;
-
-		if !std::path::Path::new(name).exists() {
-			eprintln!("File doesn't exist!");
-			std::process::exit(1);
-		}
-
-		// Takes ~60% of the loading time
-		let iimage = image::open(name).unwrap();
-
-		let size = image::image_dimensions(name).unwrap();
-
-		// TODO Optimize this loading function
-		// `&iimage.into_rgba8()` Why 16 bit? Why alpha?
-		// Shouldn't the function be determined seperately?
-		// 40% of images are 8bit
-		// 80% of images are **not** transparent
-		// Takes ~40% of the loading time
-		let image = glium::texture::RawImage2d::from_raw_rgba_reversed(
-			&iimage.into_rgba16().to_vec(),
-			size,
+	// The format is sniffed from magic bytes
+	// and only falls back to the extension for files
+	pub fn guess_format(source: &ImageSource, bytes: &[u8]) -> Option<image::ImageFormat> {
+		image::guess_format(bytes)
+			.ok()
+			.or_else(|| image::ImageFormat::from_path(source.path()?).ok())
+	}
+
+	// Decode an image, the same way for the viewer and headless commands
+	pub fn decode(
+		source: &ImageSource,
+		options: &DecodeOptions,
+	) -> Result<image::DynamicImage, LoadError> {
+		let bytes = source.bytes().map_err(|e| LoadError::from_io(source, e))?;
+		let format = UiUtils::guess_format(source, &bytes);
+		let image = UiUtils::decode_bytes(source, &bytes)?;
+
+		// RAW previews are sRGB, the profile of the TIFF around them isn't theirs
+		let icc =
+			color::icc_profile(format, &bytes, 0).filter(|_| raw::kind(source, &bytes).is_none());
+		let (mut frames, _) =
+			color::apply(vec![(image, ())], icc.as_deref(), options.color.as_ref());
+		let image = frames.remove(0).0;
+
+		if options.auto_orient {
+			Ok(UiUtils::apply_orientation(
+				image,
+				UiUtils::orientation(&bytes),
+			))
+		} else {
+			Ok(image)
+		}
+	}
+
+	// EXIF Orientation from 1 to 8, 1 is upright and also used without EXIF
+	pub fn orientation(bytes: &[u8]) -> u32 {
+		exif::Reader::new()
+			.read_from_container(&mut std::io::Cursor::new(bytes))
+			.ok()
+			.and_then(|exif| {
+				exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+					.value
+					.get_uint(0)
+			})
+			.filter(|orientation| (1..=8).contains(orientation))
+			.unwrap_or(1)
+	}
+
+	// Turn the pixels so the image is upright, mirrored ones are flipped back too
+	pub fn apply_orientation(image: image::DynamicImage, orientation: u32) -> image::DynamicImage {
+		match orientation {
+			2 => image.fliph(),
+			3 => image.rotate180(),
+			4 => image.flipv(),
+			// Transpose
+			5 => image.rotate90().fliph(),
+			6 => image.rotate90(),
+			// Transverse
+			7 => image.rotate270().fliph(),
+			8 => image.rotate270(),
+			_ => image,
+		}
+	}
+
+	pub fn decode_bytes(
+		source: &ImageSource,
+		bytes: &[u8],
+	) -> Result<image::DynamicImage, LoadError> {
+		if raw::kind(source, bytes).is_some() {
+			return raw::decode(source, bytes);
+		}
+		if svg::is_svg(source, bytes) {
+			let vector = svg::Vector::parse(source, bytes)?;
+			return UiUtils::first_raster(source, &vector);
+		}
+
+		let format = UiUtils::guess_format(source, bytes);
+		if format == Some(image::ImageFormat::Hdr) {
+			return UiUtils::decode_hdr(bytes).map_err(|e| LoadError::from_image(source, e));
+		}
+
+		let mut reader = image::io::Reader::new(std::io::Cursor::new(bytes));
+		if let Some(format) = format {
+			reader.set_format(format);
+		}
+		// The default 512MB limit would refuse huge scans and panoramas
+		reader.no_limits();
+		reader
+			.decode()
+			.or_else(|e| match (format, &e) {
+				// The image crate has no float TIFF, pages.rs does
+				(Some(image::ImageFormat::Tiff), image::ImageError::Unsupported(_)) => {
+					pages::decode(format, bytes, 0).map_err(|_| e)
+				}
+				_ => Err(e),
+			})
+			.map_err(|e| LoadError::from_image(source, e))
+	}
+
+	// Radiance HDR as floats, image::io::Reader squashes it into 8 bits
+	fn decode_hdr(bytes: &[u8]) -> image::ImageResult<image::DynamicImage> {
+		let decoder = image::codecs::hdr::HdrDecoder::new(std::io::Cursor::new(bytes))?;
+		let (width, height) = (decoder.metadata().width, decoder.metadata().height);
+		let data = decoder
+			.read_image_hdr()?
+			.into_iter()
+			.flat_map(|pixel| pixel.0)
+			.collect();
+
+		// There is a pixel for every position
+		Ok(image::DynamicImage::ImageRgb32F(
+			image::ImageBuffer::from_raw(width, height, data).unwrap(),
+		))
+	}
+
+	// One page of a file with every frame of animated GIF, APNG and WebP
+	// The decoders already composite frames, so disposal is applied
+	// Everything else is a single frame, options work like in decode
+	pub fn decode_frames(
+		source: &ImageSource,
+		page: usize,
+		options: &DecodeOptions,
+	) -> Result<Decoded, LoadError> {
+		let bytes = source.bytes().map_err(|e| LoadError::from_io(source, e))?;
+		let format = UiUtils::guess_format(source, &bytes);
+
+		// Only the embedded preview, the rest of the TIFF inside isn't pages
+		if raw::kind(source, &bytes).is_some() {
+			let frames = vec![(raw::decode(source, &bytes)?, std::time::Duration::ZERO)];
+			return Ok(UiUtils::finish(frames, None, &bytes, 0, 1, options, None));
+		}
+
+		// Starts at the size the SVG asks for, the window sharpens it when zoomed in
+		if svg::is_svg(source, &bytes) {
+			let vector = svg::Vector::parse(source, &bytes)?;
+			let frames = vec![(
+				UiUtils::first_raster(source, &vector)?,
+				std::time::Duration::ZERO,
+			)];
+			let mut decoded = UiUtils::finish(frames, None, &bytes, 0, 1, options, None);
+			decoded.vector = Some(vector);
+			return Ok(decoded);
+		}
+
+		let pages = pages::count(format, &bytes);
+		if page >= pages {
+			return Err(LoadError::Decode(
+				source.name(),
+				format!("There is no page {}, only {}", page + 1, pages),
+			));
+		}
+
+		let frames = if pages > 1 {
+			let image = pages::decode(format, &bytes, page)
+				.map_err(|e| LoadError::from_image(source, e))?;
+			vec![(image, std::time::Duration::ZERO)]
+		} else {
+			UiUtils::decode_animation(source, format, &bytes)?
+		};
+
+		Ok(UiUtils::finish(
+			frames, format, &bytes, page, pages, options, None,
+		))
+	}
+
+	// Quarter size version of big JPEGs, decodes in about a third of the time
+	// None for everything else, those decode quickly enough in full
+	pub fn decode_preview(source: &ImageSource, options: &DecodeOptions) -> Option<Decoded> {
+		use image::ImageDecoder;
+
+		// Below that the full image is about as fast as a preview
+		const MIN_PIXELS: u64 = 4_000_000;
+
+		let bytes = source.bytes().ok()?;
+		let format = UiUtils::guess_format(source, &bytes);
+		if format != Some(image::ImageFormat::Jpeg) {
+			return None;
+		}
+
+		// DCT scaling skips most of the work for the pixels that are left out
+		let mut decoder =
+			image::codecs::jpeg::JpegDecoder::new(std::io::Cursor::new(&bytes[..])).ok()?;
+		let (width, height) = decoder.dimensions();
+		if (width as u64 * height as u64) < MIN_PIXELS {
+			return None;
+		}
+		decoder
+			.scale((width / 4) as u16, (height / 4) as u16)
+			.ok()?;
+		let image = image::DynamicImage::from_decoder(decoder).ok()?;
+
+		let decoded = UiUtils::finish(
+			vec![(image, std::time::Duration::ZERO)],
+			format,
+			&bytes,
+			0,
+			1,
+			options,
+			Some((width, height)),
 		);
+		Some(decoded)
+	}
 
-		Ok(glium::texture::SrgbTexture2d::with_mipmaps(
-			display,
-			image,
-			glium::texture::MipmapsOption::AutoGeneratedMipmaps,
-		)
-		.unwrap())
+	// Color profile and orientation, the same for full images and previews
+	// preview_of is the size before turning
+	fn finish(
+		frames: Vec<(image::DynamicImage, std::time::Duration)>,
+		format: Option<image::ImageFormat>,
+		bytes: &[u8],
+		page: usize,
+		pages: usize,
+		options: &DecodeOptions,
+		preview_of: Option<(u32, u32)>,
+	) -> Decoded {
+		let icc = color::icc_profile(format, bytes, page);
+		let (frames, profile) = color::apply(frames, icc.as_deref(), options.color.as_ref());
+
+		let orientation = if options.auto_orient {
+			UiUtils::orientation(bytes)
+		} else {
+			1
+		};
+		let frames = frames
+			.into_iter()
+			.map(|(image, delay)| (UiUtils::apply_orientation(image, orientation), delay))
+			.collect();
+
+		// 5 to 8 turn by 90 degrees
+		let preview_of = preview_of.map(|(width, height)| match orientation {
+			5..=8 => (height, width),
+			_ => (width, height),
+		});
+
+		Decoded {
+			frames,
+			pages,
+			profile,
+			preview_of,
+			vector: None,
+		}
+	}
+
+	// An SVG at the size it asks for, or the biggest raster there can be
+	fn first_raster(
+		source: &ImageSource,
+		vector: &svg::Vector,
+	) -> Result<image::DynamicImage, LoadError> {
+		vector
+			.rasterize(vector.first_scale())
+			.map(image::DynamicImage::ImageRgba8)
+			.ok_or_else(|| LoadError::Decode(source.name(), "Too big to rasterize".to_string()))
+	}
+
+	// An SVG at scale times its size, color managed like decode_frames does it
+	pub fn rasterize(
+		vector: &svg::Vector,
+		scale: f32,
+		options: &DecodeOptions,
+	) -> Option<image::DynamicImage> {
+		let image = image::DynamicImage::ImageRgba8(vector.rasterize(scale)?);
+		let (mut frames, _) = color::apply(vec![(image, ())], None, options.color.as_ref());
+
+		Some(frames.remove(0).0)
+	}
+
+	// Frames of animated formats, a single frame for everything else
+	fn decode_animation(
+		source: &ImageSource,
+		format: Option<image::ImageFormat>,
+		bytes: &[u8],
+	) -> Result<Vec<(image::DynamicImage, std::time::Duration)>, LoadError> {
+		use image::AnimationDecoder;
+
+		let cursor = std::io::Cursor::new(bytes);
+		let frames = match format {
+			Some(image::ImageFormat::Gif) => {
+				image::codecs::gif::GifDecoder::new(cursor).map(|d| Some(d.into_frames()))
+			}
+			Some(image::ImageFormat::Png) => image::codecs::png::PngDecoder::new(cursor)
+				.map(|d| d.is_apng().then(|| d.apng().into_frames())),
+			Some(image::ImageFormat::WebP) => image::codecs::webp::WebPDecoder::new(cursor)
+				.map(|d| d.has_animation().then(|| d.into_frames())),
+			_ => Ok(None),
+		}
+		.map_err(|e| LoadError::from_image(source, e))?;
+
+		let frames: Vec<_> = match frames {
+			Some(frames) => frames
+				.collect_frames()
+				.map_err(|e| LoadError::from_image(source, e))?
+				.into_iter()
+				.map(|frame| {
+					let delay = std::time::Duration::from(frame.delay());
+					(image::DynamicImage::ImageRgba8(frame.into_buffer()), delay)
+				})
+				.collect(),
+			None => vec![(
+				UiUtils::decode_bytes(source, bytes)?,
+				std::time::Duration::ZERO,
+			)],
+		};
+		if frames.is_empty() {
+			return Err(LoadError::Decode(source.name(), "No frames".to_string()));
+		}
+
+		Ok(frames)
+	}
+
+	// Upload a single image, has to run on the thread with the OpenGL context
+	pub fn upload_texture<F: glium::backend::Facade>(
+		display: &F,
+		source: &ImageSource,
+		iimage: image::DynamicImage,
+	) -> Result<texture::ImageTexture, LoadError> {
+		let needed = texture::ImageTexture::needed_memory(&iimage);
+		UiUtils::check_vram(display, source, needed)?;
+
+		// Bigger images are split into tiles of this size
+		let max = display.get_context().get_capabilities().max_texture_size as u32;
+
+		// Uploaded in the decoded format, no conversion for most images
+		texture::ImageTexture::upload(display, iimage, max)
+			.map_err(|e| LoadError::Texture(source.name(), e))
+	}
+
+	// Second half of decode_frames, uploads every frame
+	pub fn upload_frames<F: glium::backend::Facade>(
+		display: &F,
+		source: &ImageSource,
+		decoded: Decoded,
+	) -> Result<animation::Animation, LoadError> {
+		let needed = decoded
+			.frames
+			.iter()
+			.map(|(image, _)| texture::ImageTexture::needed_memory(image))
+			.sum();
+		UiUtils::check_vram(display, source, needed)?;
+
+		let max = display.get_context().get_capabilities().max_texture_size as u32;
+		let frames = decoded
+			.frames
+			.into_iter()
+			.map(|(image, delay)| {
+				let mut texture = texture::ImageTexture::upload(display, image, max)
+					.map_err(|e| LoadError::Texture(source.name(), e))?;
+				if let Some((width, height)) = decoded.preview_of {
+					texture.stretch(width, height);
+				}
+				// Huge SVGs start out smaller than they ask for
+				if let Some(vector) = &decoded.vector {
+					let (width, height) = vector.size();
+					texture.stretch(width, height);
+				}
+				Ok(animation::Frame { texture, delay })
+			})
+			.collect::<Result<Vec<_>, LoadError>>()?;
+
+		let mut animation = animation::Animation::new(frames, decoded.pages, decoded.profile);
+		animation.preview = decoded.preview_of.is_some();
+		animation.raster_scale = decoded
+			.vector
+			.as_ref()
+			.map_or(1.0, |vector| vector.first_scale());
+		animation.vector = decoded.vector;
+
+		Ok(animation)
+	}
+
+	// Check free memory before uploading, drivers don't always report running out
+	fn check_vram<F: glium::backend::Facade>(
+		display: &F,
+		source: &ImageSource,
+		needed: usize,
+	) -> Result<(), LoadError> {
+		if let Some(free) = display.get_context().get_free_video_memory() {
+			if needed > free {
+				return Err(LoadError::OutOfVram {
+					name: source.name(),
+					needed,
+					free,
+				});
+			}
+		}
+
+		Ok(())
 	}
 
 	// Quad
+	// Texture rows are top to bottom, so the top of the quad gets row 0
 	pub const QUAD: [Vertex; 4] = [
 		Vertex {
 			position: [-1.0, -1.0],
-			tex_coords: [0.0, 0.0],
+			tex_coords: [0.0, 1.0],
 		},
 		Vertex {
 			position: [-1.0, 1.0],
-			tex_coords: [0.0, 1.0],
+			tex_coords: [0.0, 0.0],
 		},
 		Vertex {
 			position: [1.0, 1.0],
-			tex_coords: [1.0, 1.0],
+			tex_coords: [1.0, 0.0],
 		},
 		Vertex {
 			position: [1.0, -1.0],
-			tex_coords: [1.0, 0.0],
+			tex_coords: [1.0, 1.0],
 		},
 	];
 }
+
+#[cfg(test)]
+pub mod tests {
+	use super::*;
+
+	pub fn jpeg(width: u32, height: u32) -> Vec<u8> {
+		let mut jpeg = Vec::new();
+		image::DynamicImage::new_rgb8(width, height)
+			.write_to(
+				&mut std::io::Cursor::new(&mut jpeg),
+				image::ImageOutputFormat::Jpeg(90),
+			)
+			.unwrap();
+		jpeg
+	}
+
+	// Adds an EXIF Orientation tag
+	pub fn with_orientation(mut jpeg: Vec<u8>, orientation: u8) -> Vec<u8> {
+		// APP1 with a big endian TIFF holding a single IFD entry
+		let mut app1 = vec![0xFF, 0xE1, 0, 34];
+		app1.extend_from_slice(b"Exif\0\0MM\0\x2A\0\0\0\x08\0\x01");
+		app1.extend_from_slice(&[0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, orientation, 0, 0]);
+		app1.extend_from_slice(&[0; 4]);
+		jpeg.splice(2..2, app1);
+		jpeg
+	}
+
+	// 10x5 JPEG with an EXIF Orientation tag
+	pub fn oriented_jpeg(orientation: u8) -> Vec<u8> {
+		with_orientation(jpeg(10, 5), orientation)
+	}
+
+	// Just over 4 MP, big enough for a preview, slow to encode so only once
+	pub fn big_jpeg() -> Vec<u8> {
+		static BIG: std::sync::OnceLock<Vec<u8>> = std::sync::OnceLock::new();
+		BIG.get_or_init(|| jpeg(2560, 1600)).clone()
+	}
+
+	fn memory(bytes: Vec<u8>) -> ImageSource {
+		ImageSource::Memory("image.jpg".to_string(), std::sync::Arc::new(bytes))
+	}
+
+	// Rows of a gray image, 1 2 3 over 4 5 6 when upright
+	fn gray(rows: &[&[u8]]) -> image::DynamicImage {
+		let pixels = rows.concat();
+		image::DynamicImage::ImageLuma8(
+			image::GrayImage::from_raw(rows[0].len() as u32, rows.len() as u32, pixels).unwrap(),
+		)
+	}
+
+	#[test]
+	fn orientations() {
+		let upright = gray(&[&[1, 2, 3], &[4, 5, 6]]);
+		// How each orientation is stored, e.g. 6 has the right side of the image as its first row
+		let stored: [(u32, &[&[u8]]); 8] = [
+			(1, &[&[1, 2, 3], &[4, 5, 6]]),
+			(2, &[&[3, 2, 1], &[6, 5, 4]]),
+			(3, &[&[6, 5, 4], &[3, 2, 1]]),
+			(4, &[&[4, 5, 6], &[1, 2, 3]]),
+			(5, &[&[1, 4], &[2, 5], &[3, 6]]),
+			(6, &[&[3, 6], &[2, 5], &[1, 4]]),
+			(7, &[&[6, 3], &[5, 2], &[4, 1]]),
+			(8, &[&[4, 1], &[5, 2], &[6, 3]]),
+		];
+
+		for (orientation, rows) in stored {
+			let turned = UiUtils::apply_orientation(gray(rows), orientation);
+			assert_eq!(
+				turned.as_bytes(),
+				upright.as_bytes(),
+				"orientation {}",
+				orientation
+			);
+			assert_eq!((turned.width(), turned.height()), (3, 2));
+		}
+	}
+
+	#[test]
+	fn read_orientation() {
+		for orientation in 1..=8 {
+			assert_eq!(
+				UiUtils::orientation(&oriented_jpeg(orientation)),
+				orientation as u32
+			);
+		}
+	}
+
+	#[test]
+	fn no_orientation() {
+		let icon = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/icon.png")).unwrap();
+		assert_eq!(UiUtils::orientation(&icon), 1);
+		assert_eq!(UiUtils::orientation(&oriented_jpeg(0)), 1);
+		assert_eq!(UiUtils::orientation(&oriented_jpeg(9)), 1);
+		assert_eq!(UiUtils::orientation(b"not an image"), 1);
+	}
+
+	#[test]
+	fn previews() {
+		let options = DecodeOptions {
+			auto_orient: true,
+			color: None,
+		};
+		let size = |decoded: &Decoded| (decoded.frames[0].0.width(), decoded.frames[0].0.height());
+
+		let preview = UiUtils::decode_preview(&memory(big_jpeg()), &options).unwrap();
+		assert_eq!(size(&preview), (640, 400));
+		assert_eq!(preview.preview_of, Some((2560, 1600)));
+
+		// The full image takes its place, at full size
+		let full = UiUtils::decode_frames(&memory(big_jpeg()), 0, &options).unwrap();
+		assert_eq!(size(&full), (2560, 1600));
+		assert_eq!(full.preview_of, None);
+
+		// Turned like the full image will be
+		let turned = with_orientation(big_jpeg(), 6);
+		let preview = UiUtils::decode_preview(&memory(turned), &options).unwrap();
+		assert_eq!(size(&preview), (400, 640));
+		assert_eq!(preview.preview_of, Some((1600, 2560)));
+
+		// Small ones and other formats are quick enough in full
+		assert!(UiUtils::decode_preview(&memory(jpeg(1000, 1000)), &options).is_none());
+		let icon = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/icon.png")).unwrap();
+		assert!(UiUtils::decode_preview(&memory(icon), &options).is_none());
+	}
+}
@@ -0,0 +1,487 @@
+// view.rs
+// Where the image ends up on screen and drawing it there
+// Shared by the window and `r-liv render` so both look exactly the same
+
+extern crate glium;
+extern crate serde;
+
+use crate::cli;
+use crate::settings;
+use crate::shaders;
+use crate::texture;
+use crate::utils;
+
+use cgmath::Matrix4;
+use serde::{Deserialize, Serialize};
+
+// How float images squeeze values above 1.0 into what the screen can show
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneMapping {
+	// Everything above 1.0 is white
+	Clamp,
+	// x / (1 + x), never quite reaches white
+	Reinhard,
+	// Filmic curve, Krzysztof Narkowicz's fit of the ACES one
+	Aces,
+}
+
+impl ToneMapping {
+	// Order the tone mapping action cycles through
+	pub fn next(self) -> ToneMapping {
+		match self {
+			ToneMapping::Clamp => ToneMapping::Reinhard,
+			ToneMapping::Reinhard => ToneMapping::Aces,
+			ToneMapping::Aces => ToneMapping::Clamp,
+		}
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			ToneMapping::Clamp => "Clamp",
+			ToneMapping::Reinhard => "Reinhard",
+			ToneMapping::Aces => "ACES",
+		}
+	}
+
+	// tone_mapping uniform of the fragment shader
+	fn shader_id(self) -> i32 {
+		match self {
+			ToneMapping::Clamp => 0,
+			ToneMapping::Reinhard => 1,
+			ToneMapping::Aces => 2,
+		}
+	}
+}
+
+pub struct View {
+	// How the image is fitted before zoom
+	pub fit_mode: cli::FitMode,
+	// Zoom
+	// Multiplier for the uniform
+	pub zoom_level: f32,
+	// Pan
+	// Both are in relative pixels from the center of the image
+	// Also .1 needs to be reversed
+	pub offset: (f32, f32),
+	// Rotation
+	// Clockwise in degrees, 0, 90, 180 or 270
+	pub rotation: u16,
+}
+
+impl View {
+	pub fn new(fit_mode: cli::FitMode, zoom_level: f32, offset: (f32, f32)) -> View {
+		View {
+			fit_mode,
+			zoom_level,
+			offset,
+			rotation: 0,
+		}
+	}
+
+	// Rotate by 90 degree steps, negative is counter clockwise
+	pub fn rotate(&mut self, steps: i32) {
+		self.rotation = ((self.rotation as i32 + steps * 90).rem_euclid(360)) as u16;
+	}
+
+	// Size of the image after rotation
+	fn rotated_size(&self, image_size: (u32, u32)) -> (u32, u32) {
+		match self.rotation {
+			90 | 270 => (image_size.1, image_size.0),
+			_ => image_size,
+		}
+	}
+
+	// Size of one image pixel in screen pixels
+	pub fn texel_size(&self, image_size: (u32, u32), window_size: (f32, f32)) -> f32 {
+		let image_size = self.rotated_size(image_size);
+		let (scale_x, _) = self.fit_scale(image_size, window_size);
+
+		scale_x * window_size.0 / image_size.0 as f32 * self.zoom_level
+	}
+
+	// Scale of the quad before zoom and pan, depends on the fit mode
+	fn fit_scale(&self, image_size: (u32, u32), window_size: (f32, f32)) -> (f32, f32) {
+		let (image_width, image_height) = (image_size.0 as f32, image_size.1 as f32);
+		let (window_width, window_height) = window_size;
+
+		let image_ratio = image_width / image_height;
+		let window_ratio = window_width / window_height;
+
+		// From ArturKovacs/emulsion
+		let fit_width = (
+			1f32,
+			((window_ratio / image_ratio) * window_height).floor() / window_height,
+		);
+		let fit_height = (
+			((image_ratio / window_ratio) * window_width).floor() / window_width,
+			1f32,
+		);
+
+		match self.fit_mode {
+			cli::FitMode::Contain if image_ratio < window_ratio => fit_height,
+			cli::FitMode::Contain => fit_width,
+			cli::FitMode::Cover if image_ratio < window_ratio => fit_width,
+			cli::FitMode::Cover => fit_height,
+			cli::FitMode::Width => fit_width,
+			cli::FitMode::Height => fit_height,
+			cli::FitMode::Original => (image_width / window_width, image_height / window_height),
+		}
+	}
+
+	pub fn calculate_uniform(
+		&self,
+		image_size: (u32, u32),
+		window_width: f32,
+		window_height: f32,
+	) -> [[f32; 4]; 4] {
+		let (scale_x, scale_y) =
+			self.fit_scale(self.rotated_size(image_size), (window_width, window_height));
+
+		// Make just the scales transform
+		// Rotating has to happen in pixels, otherwise the image gets squished
+		// by the window aspect ratio, so pixels -> rotate -> back to screen space
+		let (half_width, half_height) =
+			(scale_x * window_width / 2.0, scale_y * window_height / 2.0);
+		let (half_width, half_height) = match self.rotation {
+			90 | 270 => (half_height, half_width),
+			_ => (half_width, half_height),
+		};
+		let transform =
+			Matrix4::from_nonuniform_scale(2.0 / window_width, 2.0 / window_height, 1.0)
+				* Matrix4::from_angle_z(cgmath::Deg(-(self.rotation as f32)))
+				* Matrix4::from_nonuniform_scale(half_width, half_height, 1.0);
+
+		// Pan
+		// Holy shit this is *the* worst thing I've ever written.
+		// It's goal is to offset the matrix.
+		// *Oh and x needs to be reversed
+		// The result we are looking for is f(image width in px, 0) -> (1,0)
+		// and (0, image height in px) -> (1,0).
+		// The basic is this:
+		// (window_width / 2.0 + self.offset.0) / (window_width / 2.0)
+		// BUT the center is 0.0 so
+		// (image_size_px.0 + self.offset.0) / (image_size_px.0) - 1.0
+		// BUT panning doesn't work correctly when zoomed out
+		// (image_size_px.0 * self.zoom_level + self.offset.0) / (image_size_px.0 * self.zoom_level) - 1.0
+		// BUT when zooming in again it zooms into the center but I don't care enough
+		// TODO
+
+		let image_size_px = (
+			window_width / 2.0,
+			window_height / 2.0, //? For some reason removing / scale_y is better?
+		);
+
+		let offset_x = (image_size_px.0 * self.zoom_level + self.offset.0)
+			/ (image_size_px.0 * self.zoom_level);
+
+		let offset_y = (image_size_px.1 * self.zoom_level + self.offset.1)
+			/ (image_size_px.1 * self.zoom_level);
+
+		let transform =
+			Matrix4::from_translation(cgmath::Vector3::new(offset_x - 1.0, -(offset_y - 1.0), 0.0))
+				* transform;
+
+		// Zoom
+		let transform = Matrix4::from_scale(self.zoom_level) * transform;
+
+		Into::<[[f32; 4]; 4]>::into(transform)
+	}
+
+	// Moves the -1..1 quad onto the part of the image a tile covers
+	// Row 0 is at the top, like in the texture
+	// position and size are the tile's, in image pixels
+	fn tile_transform(
+		position: (u32, u32),
+		size: (u32, u32),
+		image_size: (u32, u32),
+	) -> Matrix4<f32> {
+		let (image_width, image_height) = (image_size.0 as f32, image_size.1 as f32);
+		let (tile_width, tile_height) = (size.0 as f32, size.1 as f32);
+
+		let center_x = (position.0 as f32 + tile_width / 2.0) / image_width * 2.0 - 1.0;
+		let center_y = 1.0 - (position.1 as f32 + tile_height / 2.0) / image_height * 2.0;
+
+		Matrix4::from_translation(cgmath::Vector3::new(center_x, center_y, 0.0))
+			* Matrix4::from_nonuniform_scale(
+				tile_width / image_width,
+				tile_height / image_height,
+				1.0,
+			)
+	}
+
+	// Draw the image quad onto any surface, window or offscreen
+	// hdr is only used for float textures
+	// Errors are OpenGL problems, e.g. the shader not compiling
+	pub fn draw<F, S>(
+		&self,
+		facade: &F,
+		target: &mut S,
+		texture: &texture::ImageTexture,
+		hdr: &settings::HdrSettings,
+	) -> Result<(), String>
+	where
+		F: glium::backend::Facade,
+		S: glium::Surface,
+	{
+		// Make quad
+		let vertex_buffer = glium::VertexBuffer::new(facade, &utils::UiUtils::QUAD)
+			.map_err(|e| format!("Can't create vertex buffer: {}", e))?;
+		let index_buffer = glium::IndexBuffer::new(
+			facade,
+			glium::index::PrimitiveType::TriangleStrip,
+			&[1u16, 2, 0, 3],
+		)
+		.map_err(|e| format!("Can't create index buffer: {}", e))?;
+
+		//Calculate uniform
+		let (width, height) = target.get_dimensions();
+		let image_size = texture.dimensions();
+		let uniform: Matrix4<f32> = self
+			.calculate_uniform(image_size, width as f32, height as f32)
+			.into();
+
+		// Pixelated when zoomed in far enough to see single pixels
+		let magnify = if self.texel_size(image_size, (width as f32, height as f32)) >= 6.0 {
+			glium::uniforms::MagnifySamplerFilter::Nearest
+		} else {
+			glium::uniforms::MagnifySamplerFilter::Linear
+		};
+
+		// Get shader
+		let (vertex_shader, fragment_shader) =
+			shaders::get_shader(facade.get_context().get_opengl_version());
+
+		// Create program
+		let program = glium::Program::from_source(
+			facade,
+			vertex_shader.as_str(),
+			fragment_shader.as_str(),
+			None,
+		)
+		.map_err(|e| format!("Can't compile shader: {}", e))?;
+
+		let parameters = glium::DrawParameters {
+			blend: glium::Blend::alpha_blending(),
+			dithering: true,
+			backface_culling: glium::BackfaceCullingMode::CullingDisabled,
+			..Default::default()
+		};
+
+		// Draw a quad per tile, all with the same transform
+		// Same uniforms for both texture types, only the sampler type differs
+		for tile in &texture.tiles {
+			let tile_transform =
+				View::tile_transform((tile.x, tile.y), tile.dimensions(), image_size);
+			let matrix: [[f32; 4]; 4] = (uniform * tile_transform).into();
+
+			macro_rules! draw {
+				($texture:expr) => {
+					target.draw(
+						&vertex_buffer,
+						&index_buffer,
+						&program,
+						&glium::uniform! {
+							matrix: matrix,
+							tex: glium::uniforms::Sampler::new($texture)
+								.wrap_function(glium::uniforms::SamplerWrapFunction::Clamp)
+								.magnify_filter(magnify),
+							channels: texture.channels as i32,
+							decode_srgb: texture.decode_srgb,
+							hdr: texture.hdr,
+							exposure: hdr.exposure,
+							gamma: hdr.gamma,
+							tone_mapping: hdr.tone_mapping.shader_id(),
+						},
+						&parameters,
+					)
+				};
+			}
+			match &tile.texture {
+				texture::TextureKind::Srgb(srgb) => draw!(srgb),
+				texture::TextureKind::Linear(linear) => draw!(linear),
+			}
+			.map_err(|e| format!("Can't draw image: {}", e))?;
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use cgmath::Vector4;
+
+	const WINDOW: (f32, f32) = (200.0, 100.0);
+	const IMAGE: (u32, u32) = (40, 10);
+
+	fn view(fit_mode: cli::FitMode, rotation: u16) -> View {
+		View {
+			rotation,
+			..View::new(fit_mode, 1.0, (0.0, 0.0))
+		}
+	}
+
+	// Window pixel, from the top left, that an image pixel is drawn at
+	fn on_screen(view: &View, point: (f32, f32)) -> (f32, f32) {
+		let matrix: Matrix4<f32> = view.calculate_uniform(IMAGE, WINDOW.0, WINDOW.1).into();
+		let quad = Vector4::new(
+			point.0 / IMAGE.0 as f32 * 2.0 - 1.0,
+			1.0 - point.1 / IMAGE.1 as f32 * 2.0,
+			0.0,
+			1.0,
+		);
+		let ndc = matrix * quad;
+
+		(
+			(ndc.x + 1.0) / 2.0 * WINDOW.0,
+			(1.0 - ndc.y) / 2.0 * WINDOW.1,
+		)
+	}
+
+	// Top left and bottom right corner of the image on screen
+	fn corners(view: &View) -> [(f32, f32); 2] {
+		let (width, height) = (IMAGE.0 as f32, IMAGE.1 as f32);
+		[
+			on_screen(view, (0.0, 0.0)),
+			on_screen(view, (width, height)),
+		]
+	}
+
+	fn assert_near(actual: [(f32, f32); 2], expected: [(f32, f32); 2]) {
+		for (a, e) in actual.iter().zip(expected.iter()) {
+			assert!(
+				(a.0 - e.0).abs() < 1e-3 && (a.1 - e.1).abs() < 1e-3,
+				"{:?} != {:?}",
+				actual,
+				expected
+			);
+		}
+	}
+
+	#[test]
+	fn fit_scales() {
+		let scale = |fit_mode, rotation| {
+			let view = view(fit_mode, rotation);
+			view.fit_scale(view.rotated_size(IMAGE), WINDOW)
+		};
+
+		assert_eq!(scale(cli::FitMode::Contain, 0), (1.0, 0.5));
+		assert_eq!(scale(cli::FitMode::Cover, 0), (2.0, 1.0));
+		assert_eq!(scale(cli::FitMode::Width, 0), (1.0, 0.5));
+		assert_eq!(scale(cli::FitMode::Height, 0), (2.0, 1.0));
+		assert_eq!(scale(cli::FitMode::Original, 0), (0.2, 0.1));
+
+		// Standing up, 10x40
+		for rotation in [90, 270] {
+			assert_eq!(scale(cli::FitMode::Contain, rotation), (0.125, 1.0));
+			assert_eq!(scale(cli::FitMode::Cover, rotation), (1.0, 8.0));
+			assert_eq!(scale(cli::FitMode::Width, rotation), (1.0, 8.0));
+			assert_eq!(scale(cli::FitMode::Height, rotation), (0.125, 1.0));
+			assert_eq!(scale(cli::FitMode::Original, rotation), (0.05, 0.4));
+		}
+		assert_eq!(
+			scale(cli::FitMode::Contain, 180),
+			scale(cli::FitMode::Contain, 0)
+		);
+	}
+
+	#[test]
+	fn fit_modes() {
+		let fitted = |fit_mode| corners(&view(fit_mode, 0));
+
+		assert_near(fitted(cli::FitMode::Contain), [(0.0, 25.0), (200.0, 75.0)]);
+		assert_near(fitted(cli::FitMode::Cover), [(-100.0, 0.0), (300.0, 100.0)]);
+		assert_near(fitted(cli::FitMode::Width), [(0.0, 25.0), (200.0, 75.0)]);
+		assert_near(
+			fitted(cli::FitMode::Height),
+			[(-100.0, 0.0), (300.0, 100.0)],
+		);
+		assert_near(
+			fitted(cli::FitMode::Original),
+			[(80.0, 45.0), (120.0, 55.0)],
+		);
+	}
+
+	#[test]
+	fn rotations() {
+		// The top left corner of the image follows the rotation clockwise
+		let turned = |fit_mode, rotation| corners(&view(fit_mode, rotation));
+
+		assert_near(
+			turned(cli::FitMode::Contain, 90),
+			[(112.5, 0.0), (87.5, 100.0)],
+		);
+		assert_near(
+			turned(cli::FitMode::Contain, 180),
+			[(200.0, 75.0), (0.0, 25.0)],
+		);
+		assert_near(
+			turned(cli::FitMode::Contain, 270),
+			[(87.5, 100.0), (112.5, 0.0)],
+		);
+		assert_near(
+			turned(cli::FitMode::Cover, 90),
+			[(200.0, -350.0), (0.0, 450.0)],
+		);
+		assert_near(
+			turned(cli::FitMode::Width, 270),
+			[(0.0, 450.0), (200.0, -350.0)],
+		);
+		assert_near(
+			turned(cli::FitMode::Height, 180),
+			[(300.0, 100.0), (-100.0, 0.0)],
+		);
+		assert_near(
+			turned(cli::FitMode::Original, 90),
+			[(105.0, 30.0), (95.0, 70.0)],
+		);
+	}
+
+	#[test]
+	fn zoom_and_pan() {
+		let zoomed = View::new(cli::FitMode::Contain, 2.0, (0.0, 0.0));
+		assert_near(corners(&zoomed), [(-100.0, 0.0), (300.0, 100.0)]);
+
+		// Offset is in screen pixels at any zoom, y goes down
+		let panned = View::new(cli::FitMode::Contain, 1.0, (10.0, 5.0));
+		assert_near(corners(&panned), [(10.0, 30.0), (210.0, 80.0)]);
+		let both = View::new(cli::FitMode::Contain, 2.0, (10.0, 5.0));
+		assert_near(corners(&both), [(-90.0, 5.0), (310.0, 105.0)]);
+	}
+
+	#[test]
+	fn texel_sizes() {
+		assert_eq!(
+			view(cli::FitMode::Contain, 0).texel_size(IMAGE, WINDOW),
+			5.0
+		);
+		assert_eq!(
+			view(cli::FitMode::Original, 90).texel_size(IMAGE, WINDOW),
+			1.0
+		);
+		let zoomed = View::new(cli::FitMode::Contain, 2.0, (0.0, 0.0));
+		assert_eq!(zoomed.texel_size(IMAGE, WINDOW), 10.0);
+	}
+
+	#[test]
+	fn tiles() {
+		// Right half of the top row of a 2x2 grid
+		let transform = View::tile_transform((50, 0), (50, 25), (100, 50));
+		let top_left = transform * Vector4::new(-1.0, 1.0, 0.0, 1.0);
+		let bottom_right = transform * Vector4::new(1.0, -1.0, 0.0, 1.0);
+		assert_eq!((top_left.x, top_left.y), (0.0, 1.0));
+		assert_eq!((bottom_right.x, bottom_right.y), (1.0, 0.0));
+
+		// One tile covers the whole quad
+		let whole = View::tile_transform((0, 0), (100, 50), (100, 50));
+		assert_eq!(whole, Matrix4::from_scale(1.0));
+
+		// Uneven last tile
+		let transform = View::tile_transform((64, 64), (36, 1), (100, 65));
+		let center = transform * Vector4::new(0.0, 0.0, 0.0, 1.0);
+		assert!((center.x - 0.64).abs() < 1e-6);
+		assert!((center.y - (1.0 - 64.5 / 65.0 * 2.0)).abs() < 1e-6);
+	}
+}